@@ -0,0 +1,69 @@
+//! Smooth movement of nodes to the positions chosen by the layout.
+
+use bevy::prelude::*;
+
+use crate::{Pinned, VisualiserSettings};
+
+/// Where the layout wants a node to be. Instead of jumping there, the node
+/// eases from wherever it was when the target changed.
+#[derive(Component, Default)]
+pub(crate) struct LayoutTarget {
+    position: Option<Vec2>,
+    from: Vec2,
+    elapsed: f32,
+    finished: bool,
+}
+
+impl LayoutTarget {
+    /// Update the target, `current` is the current position of the node.
+    /// The first target a node gets is jumped to directly.
+    pub(crate) fn set(&mut self, position: Vec2, current: Vec2) {
+        match self.position {
+            None => {
+                self.position = Some(position);
+                self.from = position;
+                self.elapsed = 0.;
+                self.finished = false;
+            }
+            Some(old) => {
+                let moved_away = self.finished && current.distance_squared(position) > 0.01;
+                if old.distance_squared(position) > 0.01 || moved_away {
+                    self.position = Some(position);
+                    self.from = current;
+                    self.elapsed = 0.;
+                    self.finished = false;
+                }
+            }
+        }
+    }
+}
+
+/// Ease in and out so that movement starts and ends gently
+pub(crate) fn ease(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+pub(crate) fn move_towards_targets(
+    mut q_targets: Query<(&mut Transform, &mut LayoutTarget), Without<Pinned>>,
+    settings: Res<VisualiserSettings>,
+    time: Res<Time>,
+) {
+    let duration = settings.transition_duration.as_secs_f32();
+    for (mut transform, mut target) in &mut q_targets {
+        if target.finished {
+            continue;
+        }
+        let Some(position) = target.position else {
+            continue;
+        };
+        target.elapsed += time.delta_seconds();
+        let t = if duration > 0. {
+            (target.elapsed / duration).min(1.)
+        } else {
+            1.
+        };
+        let xy = target.from.lerp(position, ease(t));
+        transform.translation = xy.extend(transform.translation.z);
+        target.finished = t >= 1.;
+    }
+}
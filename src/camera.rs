@@ -0,0 +1,372 @@
+//! Moving and zooming the view.
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    window::PrimaryWindow,
+};
+use knyst::graph::NodeId;
+
+use crate::{
+    animation::ease, cursor_over_ui, drag::Selected, node_height, node_size, GraphInputs,
+    GraphOutputs, Node, VisualiserSettings,
+};
+
+/// Marks the camera the visualiser is seen through and holds how it is
+/// controlled. Change the fields on the entity to tune the controls.
+#[derive(Component, Clone, Debug)]
+pub struct GameCamera {
+    /// Keyboard panning speed in screen pixels per second
+    pub pan_speed: f32,
+    /// How much one step of the mouse wheel zooms, as a fraction of the
+    /// current zoom
+    pub zoom_speed: f32,
+    /// Smallest allowed projection scale, i.e. the furthest zoomed in
+    pub min_scale: f32,
+    /// Largest allowed projection scale, i.e. the furthest zoomed out
+    pub max_scale: f32,
+    /// Mouse buttons that pan the view while held down
+    pub drag_buttons: Vec<MouseButton>,
+    /// Scroll the view when the cursor is close to the edge of the window
+    pub edge_scroll: bool,
+    /// Distance from the edge of the window in pixels where edge scrolling starts
+    pub edge_scroll_margin: f32,
+    /// Edge scrolling speed in screen pixels per second
+    pub edge_scroll_speed: f32,
+}
+
+impl Default for GameCamera {
+    fn default() -> Self {
+        Self {
+            pan_speed: 600.,
+            zoom_speed: 0.1,
+            min_scale: 0.1,
+            max_scale: 20.,
+            drag_buttons: vec![MouseButton::Middle, MouseButton::Right],
+            edge_scroll: false,
+            edge_scroll_margin: 50.,
+            edge_scroll_speed: 300.,
+        }
+    }
+}
+
+/// Send this event to move the camera to a part of the graph
+#[derive(Event, Clone, Copy, Debug)]
+pub enum CameraCommand {
+    /// Show every node of the graph
+    FitAll,
+    /// Show every selected node
+    FitSelection,
+    /// Center the view on a node of the shown graph
+    FocusNode(NodeId),
+}
+
+/// A camera movement started by a [`CameraCommand`], cancelled as soon as the
+/// camera is moved by hand
+#[derive(Resource, Default)]
+pub(crate) struct CameraTransition(Option<Transition>);
+
+impl CameraTransition {
+    pub(crate) fn cancel(&mut self) {
+        self.0 = None;
+    }
+}
+
+struct Transition {
+    from: Vec2,
+    from_scale: f32,
+    to: Vec2,
+    to_scale: f32,
+    elapsed: f32,
+}
+
+/// Pixels of a touchpad scroll event that count as scrolling one line
+const PIXELS_PER_LINE: f32 = 20.;
+/// World space to leave free around the nodes when fitting them in the view
+const FIT_MARGIN: f32 = 40.;
+/// Fitting never zooms in further than this so that small graphs and single
+/// nodes aren't blown up
+const FIT_MIN_SCALE: f32 = 1.;
+
+pub(crate) fn zoom_camera(
+    mut scroll_events: EventReader<MouseWheel>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    mut q_camera: Query<(
+        &GameCamera,
+        &Camera,
+        &GlobalTransform,
+        &mut Transform,
+        &mut OrthographicProjection,
+    )>,
+    q_interactions: Query<&Interaction>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let lines: f32 = scroll_events
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    // Scrolling over a panel doesn't zoom the graph behind it
+    if lines == 0. || cursor_over_ui(&q_interactions) {
+        return;
+    }
+    let Ok((game_camera, camera, global_transform, mut transform, mut projection)) =
+        q_camera.get_single_mut()
+    else {
+        return;
+    };
+    transition.cancel();
+    let old_scale = projection.scale;
+    projection.scale = (old_scale * (1. + game_camera.zoom_speed).powf(-lines))
+        .clamp(game_camera.min_scale, game_camera.max_scale);
+    // Keep the point under the cursor where it is
+    let cursor = q_windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+        .and_then(|cursor| camera.viewport_to_world_2d(global_transform, cursor));
+    if let Some(cursor) = cursor {
+        let center = transform.translation.xy();
+        let new_center = cursor + (center - cursor) * (projection.scale / old_scale);
+        transform.translation = new_center.extend(transform.translation.z);
+    }
+}
+
+pub(crate) fn drag_pan_camera(
+    mouse: Res<Input<MouseButton>>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    mut q_camera: Query<(&GameCamera, &mut Transform, &OrthographicProjection)>,
+    mut last_cursor: Local<Option<Vec2>>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let Ok((game_camera, mut transform, projection)) = q_camera.get_single_mut() else {
+        return;
+    };
+    let cursor = q_windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    if !game_camera.drag_buttons.iter().any(|b| mouse.pressed(*b)) {
+        *last_cursor = None;
+        return;
+    }
+    if let (Some(cursor), Some(last)) = (cursor, *last_cursor) {
+        let delta = cursor - last;
+        if delta != Vec2::ZERO {
+            transition.cancel();
+        }
+        // Window coordinates grow downwards, world coordinates upwards
+        transform.translation.x -= delta.x * projection.scale;
+        transform.translation.y += delta.y * projection.scale;
+    }
+    *last_cursor = cursor;
+}
+
+pub(crate) fn keyboard_pan_camera(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut q_camera: Query<(&GameCamera, &mut Transform, &OrthographicProjection)>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let Ok((game_camera, mut transform, projection)) = q_camera.get_single_mut() else {
+        return;
+    };
+    let mut direction = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::A, KeyCode::Left]) {
+        direction.x -= 1.;
+    }
+    if keys.any_pressed([KeyCode::D, KeyCode::Right]) {
+        direction.x += 1.;
+    }
+    if keys.any_pressed([KeyCode::W, KeyCode::Up]) {
+        direction.y += 1.;
+    }
+    if keys.any_pressed([KeyCode::S, KeyCode::Down]) {
+        direction.y -= 1.;
+    }
+    if direction == Vec2::ZERO {
+        return;
+    }
+    transition.cancel();
+    let movement = direction.normalize_or_zero()
+        * game_camera.pan_speed
+        * projection.scale
+        * time.delta_seconds();
+    transform.translation += movement.extend(0.);
+}
+
+pub(crate) fn edge_scroll_camera(
+    time: Res<Time>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    mut q_camera: Query<(&GameCamera, &mut Transform, &OrthographicProjection)>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let Ok((game_camera, mut transform, projection)) = q_camera.get_single_mut() else {
+        return;
+    };
+    if !game_camera.edge_scroll {
+        return;
+    }
+    let Ok(window) = q_windows.get_single() else {
+        return;
+    };
+    let Some(position) = window.cursor_position() else {
+        return;
+    };
+    let margin = game_camera.edge_scroll_margin;
+    let mut direction = Vec2::ZERO;
+    if position.x < margin {
+        direction.x -= 1.;
+    }
+    if position.y < margin {
+        direction.y += 1.;
+    }
+    if position.x > window.width() - margin {
+        direction.x += 1.;
+    }
+    if position.y > window.height() - margin {
+        direction.y -= 1.;
+    }
+    if direction == Vec2::ZERO {
+        return;
+    }
+    transition.cancel();
+    let movement = direction.normalize_or_zero()
+        * game_camera.edge_scroll_speed
+        * projection.scale
+        * time.delta_seconds();
+    transform.translation += movement.extend(0.);
+}
+
+pub(crate) fn send_camera_commands(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    mut camera_commands: EventWriter<CameraCommand>,
+) {
+    if settings
+        .fit_all_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        camera_commands.send(CameraCommand::FitAll);
+    }
+    if settings
+        .fit_selection_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        camera_commands.send(CameraCommand::FitSelection);
+    }
+}
+
+/// The smallest rectangle containing all of `rects`
+fn bounds(rects: impl IntoIterator<Item = Rect>) -> Option<Rect> {
+    rects.into_iter().reduce(|a, b| a.union(b))
+}
+
+pub(crate) fn handle_camera_commands(
+    mut camera_commands: EventReader<CameraCommand>,
+    settings: Res<VisualiserSettings>,
+    q_camera: Query<(&GameCamera, &Camera, &Transform, &OrthographicProjection)>,
+    q_nodes: Query<(&Node, &Transform, Has<Selected>)>,
+    q_graph_outputs: Query<(&GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(&GraphInputs, &Transform)>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let Ok((game_camera, camera, transform, projection)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(viewport) = camera.logical_viewport_size() else {
+        return;
+    };
+    let node_rect = |node: &Node, transform: &Transform| {
+        Rect::from_center_size(transform.translation.xy(), node_size(node, &settings))
+    };
+    let io_rect = |num_channels, transform: &Transform| {
+        Rect::from_center_size(
+            transform.translation.xy(),
+            Vec2::new(
+                settings.node_width,
+                node_height(num_channels, num_channels, &settings),
+            ),
+        )
+    };
+    for command in camera_commands.read() {
+        let rect = match command {
+            CameraCommand::FitAll => bounds(
+                q_nodes
+                    .iter()
+                    .map(|(node, transform, _)| node_rect(node, transform))
+                    .chain(
+                        q_graph_outputs
+                            .iter()
+                            .map(|(go, transform)| io_rect(go.num_outputs, transform)),
+                    )
+                    .chain(
+                        q_graph_inputs
+                            .iter()
+                            .map(|(gi, transform)| io_rect(gi.num_inputs, transform)),
+                    ),
+            ),
+            CameraCommand::FitSelection => bounds(
+                q_nodes
+                    .iter()
+                    .filter(|(_, _, selected)| *selected)
+                    .map(|(node, transform, _)| node_rect(node, transform)),
+            ),
+            CameraCommand::FocusNode(id) => {
+                let rect = q_nodes
+                    .iter()
+                    .find(|(node, _, _)| node.id == *id)
+                    .map(|(node, transform, _)| node_rect(node, transform));
+                if rect.is_none() {
+                    warn!("Can't focus on node {id:?}, it isn't in the shown graph");
+                }
+                rect
+            }
+        };
+        let Some(rect) = rect else {
+            continue;
+        };
+        let size = rect.size() + Vec2::splat(FIT_MARGIN * 2.);
+        let scale = (size / viewport)
+            .max_element()
+            .max(FIT_MIN_SCALE)
+            .clamp(game_camera.min_scale, game_camera.max_scale);
+        transition.0 = Some(Transition {
+            from: transform.translation.xy(),
+            from_scale: projection.scale,
+            to: rect.center(),
+            to_scale: scale,
+            elapsed: 0.,
+        });
+    }
+}
+
+pub(crate) fn animate_camera(
+    settings: Res<VisualiserSettings>,
+    time: Res<Time>,
+    mut q_camera: Query<(&mut Transform, &mut OrthographicProjection), With<GameCamera>>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    let Some(movement) = transition.0.as_mut() else {
+        return;
+    };
+    let Ok((mut transform, mut projection)) = q_camera.get_single_mut() else {
+        return;
+    };
+    movement.elapsed += time.delta_seconds();
+    let duration = settings.transition_duration.as_secs_f32();
+    let t = if duration > 0. {
+        (movement.elapsed / duration).min(1.)
+    } else {
+        1.
+    };
+    let eased = ease(t);
+    let xy = movement.from.lerp(movement.to, eased);
+    transform.translation = xy.extend(transform.translation.z);
+    projection.scale = movement.from_scale + (movement.to_scale - movement.from_scale) * eased;
+    if t >= 1. {
+        transition.0 = None;
+    }
+}
@@ -0,0 +1,243 @@
+//! Capturing the samples of a single node output for closer inspection.
+//!
+//! Like the level meters, samples don't come from the inspection but from a
+//! [`SampleCapture`], which can be backed by knyst or by a
+//! [`MockSampleCapture`] fed with synthetic signals.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use knyst::graph::NodeId;
+
+use crate::{
+    cursor_over_ui, cursor_world_position, drag::DRAG_THRESHOLD, navigation::GraphView,
+    node_height, port_at, port_y_offset, scope, spectrum, GameCamera, KnystData, Node,
+    VisualiserSettings,
+};
+
+/// A source of recent samples from node outputs
+pub trait SampleCapture: Send + Sync + 'static {
+    /// Start recording output `channel` of `node`
+    fn start(&self, node: NodeId, channel: usize);
+    /// Stop recording output `channel` of `node`
+    fn stop(&self, node: NodeId, channel: usize);
+    /// Up to `num_samples` of the most recently recorded samples, oldest first
+    fn latest_samples(&self, node: NodeId, channel: usize, num_samples: usize) -> Vec<f32>;
+    /// Sample rate of the recorded signal in Hz
+    fn sample_rate(&self) -> f32;
+}
+
+/// A [`SampleCapture`] that records whatever samples are pushed to it. Clones
+/// share the same buffers, so keep one to feed it from another thread.
+#[derive(Clone)]
+pub struct MockSampleCapture {
+    sample_rate: f32,
+    capacity: usize,
+    buffers: Arc<Mutex<HashMap<(NodeId, usize), VecDeque<f32>>>>,
+}
+
+impl MockSampleCapture {
+    /// Keeps the latest `capacity` samples of every output
+    pub fn new(sample_rate: f32, capacity: usize) -> Self {
+        Self {
+            sample_rate,
+            capacity,
+            buffers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Record `samples` as the next output of the node. Outputs that haven't
+    /// been started are recorded as well.
+    pub fn push_samples(&self, node: NodeId, channel: usize, samples: &[f32]) {
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = buffers.entry((node, channel)).or_default();
+        buffer.extend(samples);
+        let excess = buffer.len().saturating_sub(self.capacity);
+        buffer.drain(..excess);
+    }
+}
+
+impl SampleCapture for MockSampleCapture {
+    fn start(&self, node: NodeId, channel: usize) {
+        self.buffers
+            .lock()
+            .unwrap()
+            .entry((node, channel))
+            .or_default();
+    }
+    fn stop(&self, _node: NodeId, _channel: usize) {}
+    fn latest_samples(&self, node: NodeId, channel: usize, num_samples: usize) -> Vec<f32> {
+        let buffers = self.buffers.lock().unwrap();
+        let Some(buffer) = buffers.get(&(node, channel)) else {
+            return Vec::new();
+        };
+        let skip = buffer.len().saturating_sub(num_samples);
+        buffer.iter().skip(skip).copied().collect()
+    }
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+}
+
+/// The [`SampleCapture`] used by the visualiser. Output ports can only be
+/// probed when this resource exists.
+#[derive(Resource, Clone)]
+pub struct SampleCaptures(pub Arc<dyn SampleCapture>);
+
+/// The output being looked at in the scope
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ProbedOutput {
+    pub(crate) node: NodeId,
+    pub(crate) channel: usize,
+    /// Node and channel name to show in panels
+    pub(crate) label: String,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct Probe {
+    pub(crate) output: Option<ProbedOutput>,
+}
+
+/// Holds the panels looking at the probed output, side by side in the top
+/// right corner
+pub(crate) fn setup_probe_panels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load(&settings.font_path),
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(5.),
+                right: Val::Px(10.),
+                column_gap: Val::Px(10.),
+                align_items: AlignItems::FlexStart,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            scope::spawn_scope(parent, &text_style, &settings);
+            spectrum::spawn_spectrum(parent, &text_style, &settings);
+        });
+}
+
+/// Probe an output when its port on the right edge of a node is clicked.
+/// Pressing on the port and moving away drags a new connection instead, so
+/// only a release close to where the button was pressed counts.
+pub(crate) fn probe_output_on_click(
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_projection: Query<&OrthographicProjection, With<GameCamera>>,
+    q_nodes: Query<(&Node, &Transform)>,
+    q_interactions: Query<&Interaction>,
+    mut probe: ResMut<Probe>,
+    mut pressed: Local<Option<(Vec2, NodeId, usize)>>,
+) {
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    if mouse.just_released(MouseButton::Left) {
+        if let Some((start, node, channel)) = pressed.take() {
+            let scale = q_projection.get_single().map_or(1., |p| p.scale);
+            if cursor.distance(start) < DRAG_THRESHOLD * scale {
+                probe_output(node, channel, &knyst_data, &graph_view, &mut probe);
+            }
+        }
+    }
+    if !mouse.just_pressed(MouseButton::Left) || cursor_over_ui(&q_interactions) {
+        return;
+    }
+    let clicked = q_nodes.iter().find_map(|(node, transform)| {
+        port_at(
+            cursor,
+            transform.translation.xy(),
+            node_height(node.num_inputs, node.num_outputs, &settings),
+            node.num_outputs,
+            true,
+            &settings,
+        )
+        .map(|channel| (node.id, channel))
+    });
+    *pressed = clicked.map(|(node, channel)| (cursor, node, channel));
+}
+
+fn probe_output(
+    node: NodeId,
+    channel: usize,
+    knyst_data: &KnystData,
+    graph_view: &GraphView,
+    probe: &mut Probe,
+) {
+    let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+    let label = inspection
+        .nodes
+        .iter()
+        .find(|n| n.address == node)
+        .map(|n| match n.output_channels.get(channel) {
+            Some(output) => format!("{} {}", n.name, output),
+            None => format!("{} {}", n.name, channel),
+        })
+        .unwrap_or_else(|| format!("output {channel}"));
+    probe.output = Some(ProbedOutput {
+        node,
+        channel,
+        label,
+    });
+}
+
+/// Tell the capture which output to record whenever the probe moves
+pub(crate) fn update_capture(
+    probe: Res<Probe>,
+    captures: Res<SampleCaptures>,
+    mut recording: Local<Option<(NodeId, usize)>>,
+) {
+    if !probe.is_changed() {
+        return;
+    }
+    let wanted = probe.output.as_ref().map(|o| (o.node, o.channel));
+    if wanted == *recording {
+        return;
+    }
+    if let Some((node, channel)) = *recording {
+        captures.0.stop(node, channel);
+    }
+    if let Some((node, channel)) = wanted {
+        captures.0.start(node, channel);
+    }
+    *recording = wanted;
+}
+
+/// Mark the probed port
+pub(crate) fn draw_probe(
+    mut gizmos: Gizmos,
+    probe: Res<Probe>,
+    settings: Res<VisualiserSettings>,
+    q_nodes: Query<(&Node, &Transform)>,
+) {
+    let Some(output) = &probe.output else {
+        return;
+    };
+    let Some((node, transform)) = q_nodes.iter().find(|(node, _)| node.id == output.node) else {
+        return;
+    };
+    let height = node_height(node.num_inputs, node.num_outputs, &settings);
+    let port = transform.translation.xy()
+        + Vec2::new(
+            settings.node_width * 0.5,
+            port_y_offset(output.channel, height, &settings),
+        );
+    gizmos.circle_2d(port, settings.row_height * 0.4, Color::GREEN);
+}
@@ -0,0 +1,175 @@
+//! Selecting nodes and moving them around with the mouse.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    cursor_over_ui, cursor_world_position, node_size, try_insert, wiring::ConnectionDrag,
+    GameCamera, Node, VisualiserSettings,
+};
+
+/// The cursor has to move this many screen pixels with the button held down
+/// before a click turns into a drag. Scale it by the zoom to compare it with
+/// world distances.
+pub(crate) const DRAG_THRESHOLD: f32 = 4.;
+
+/// A node that has been moved by hand. The automatic layouts leave it where
+/// it is and place the other nodes around it.
+#[derive(Component)]
+pub struct Pinned;
+
+/// A node that has been clicked. Hold shift to select more than one node.
+#[derive(Component)]
+pub struct Selected;
+
+/// The node currently being dragged
+#[derive(Resource, Default)]
+pub(crate) struct Dragging {
+    entity: Option<Entity>,
+    /// From the cursor to the center of the node
+    offset: Vec2,
+    /// Where the button was pressed
+    start: Vec2,
+    started: bool,
+}
+
+impl Dragging {
+    /// True while a node is being moved, as opposed to just clicked
+    pub(crate) fn is_dragging(&self) -> bool {
+        self.entity.is_some() && self.started
+    }
+}
+
+pub(crate) fn drag_nodes(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_projection: Query<&OrthographicProjection, With<GameCamera>>,
+    mut q_nodes: Query<(Entity, &Node, &mut Transform)>,
+    q_selected: Query<Entity, With<Selected>>,
+    q_interactions: Query<&Interaction>,
+    connection_drag: Res<ConnectionDrag>,
+    mut dragging: ResMut<Dragging>,
+) {
+    let add_to_selection = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    // The node being held can be despawned by a new inspection
+    if dragging
+        .entity
+        .is_some_and(|entity| !q_nodes.contains(entity))
+    {
+        dragging.entity = None;
+    }
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    // Pressing on an output port starts a new connection instead
+    if mouse.just_pressed(MouseButton::Left)
+        && !cursor_over_ui(&q_interactions)
+        && !connection_drag.is_dragging()
+    {
+        let grabbed = q_nodes
+            .iter()
+            .filter(|(_, node, transform)| {
+                Rect::from_center_size(transform.translation.xy(), node_size(node, &settings))
+                    .contains(cursor)
+            })
+            // The node drawn on top
+            .max_by(|a, b| a.2.translation.z.total_cmp(&b.2.translation.z));
+        if let Some((entity, _, transform)) = grabbed {
+            *dragging = Dragging {
+                entity: Some(entity),
+                offset: transform.translation.xy() - cursor,
+                start: cursor,
+                started: false,
+            };
+        } else if !add_to_selection {
+            // Clicking the background clears the selection
+            for entity in &q_selected {
+                commands.entity(entity).remove::<Selected>();
+            }
+        }
+    }
+    if !mouse.pressed(MouseButton::Left) {
+        // A click that never turned into a drag selects the node
+        if let (Some(entity), false) = (dragging.entity, dragging.started) {
+            if add_to_selection {
+                if q_selected.contains(entity) {
+                    commands.entity(entity).remove::<Selected>();
+                } else {
+                    try_insert(&mut commands, entity, Selected);
+                }
+            } else {
+                for selected in q_selected.iter().filter(|e| *e != entity) {
+                    commands.entity(selected).remove::<Selected>();
+                }
+                try_insert(&mut commands, entity, Selected);
+            }
+        }
+        dragging.entity = None;
+        return;
+    }
+    let Some(entity) = dragging.entity else {
+        return;
+    };
+    let scale = q_projection.get_single().map_or(1., |p| p.scale);
+    if !dragging.started && cursor.distance(dragging.start) < DRAG_THRESHOLD * scale {
+        return;
+    }
+    if !dragging.started {
+        dragging.started = true;
+        try_insert(&mut commands, entity, Pinned);
+    }
+    if let Ok((_, _, mut transform)) = q_nodes.get_mut(entity) {
+        transform.translation = (cursor + dragging.offset).extend(transform.translation.z);
+    }
+}
+
+/// Give every pinned node back to the automatic layout
+pub(crate) fn unpin_nodes(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    q_pinned: Query<Entity, With<Pinned>>,
+) {
+    if settings
+        .unpin_all_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        for entity in &q_pinned {
+            commands.entity(entity).remove::<Pinned>();
+        }
+    }
+}
+
+/// Outline pinned nodes so that it's clear why they don't follow the layout
+pub(crate) fn draw_pins(
+    mut gizmos: Gizmos,
+    settings: Res<VisualiserSettings>,
+    q_pinned: Query<(&Node, &Transform), With<Pinned>>,
+) {
+    for (node, transform) in &q_pinned {
+        gizmos.rect_2d(
+            transform.translation.xy(),
+            0.,
+            node_size(node, &settings) + Vec2::splat(4.),
+            Color::WHITE,
+        );
+    }
+}
+
+pub(crate) fn draw_selection(
+    mut gizmos: Gizmos,
+    settings: Res<VisualiserSettings>,
+    q_selected: Query<(&Node, &Transform), With<Selected>>,
+) {
+    for (node, transform) in &q_selected {
+        gizmos.rect_2d(
+            transform.translation.xy(),
+            0.,
+            node_size(node, &settings) + Vec2::splat(8.),
+            Color::YELLOW,
+        );
+    }
+}
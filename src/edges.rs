@@ -0,0 +1,155 @@
+//! The shape of the lines drawn for edges.
+
+use bevy::prelude::*;
+
+use crate::{edge_end_port, EdgeEnd, NodeEdge, VisualiserSettings};
+
+/// How edges are drawn between an output port and an input port
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeStyle {
+    /// A straight line from port to port
+    Straight,
+    /// A Bézier curve leaving and entering the ports horizontally
+    #[default]
+    Curved,
+    /// Horizontal and vertical segments only
+    Orthogonal,
+}
+
+/// Number of straight segments a curved edge is drawn with
+const CURVE_SEGMENTS: usize = 24;
+/// The shortest distance the curve goes straight out of a port before bending
+const MIN_CURVE_HANDLE: f32 = 40.;
+/// Length of the sides of the arrowhead
+const ARROW_LENGTH: f32 = 8.;
+/// Angle between the sides of the arrowhead and the edge
+const ARROW_ANGLE: f32 = 0.45;
+/// Length of the dashes and of the gaps between them
+const DASH_LENGTH: f32 = 10.;
+
+/// Points along an edge from the output port at `start` to the input port at
+/// `end`. `clearance` is how far an orthogonal edge goes out of a port before
+/// turning when it has to go backwards.
+pub(crate) fn edge_path(start: Vec2, end: Vec2, style: EdgeStyle, clearance: f32) -> Vec<Vec2> {
+    match style {
+        EdgeStyle::Straight => vec![start, end],
+        EdgeStyle::Curved => {
+            // Going backwards the handles get longer so the edge loops around
+            // instead of cutting through the nodes
+            let handle = ((end.x - start.x).abs() * 0.5).max(MIN_CURVE_HANDLE);
+            let c1 = start + Vec2::new(handle, 0.);
+            let c2 = end - Vec2::new(handle, 0.);
+            (0..=CURVE_SEGMENTS)
+                .map(|i| {
+                    let t = i as f32 / CURVE_SEGMENTS as f32;
+                    let u = 1. - t;
+                    start * (u * u * u)
+                        + c1 * (3. * u * u * t)
+                        + c2 * (3. * u * t * t)
+                        + end * (t * t * t)
+                })
+                .collect()
+        }
+        EdgeStyle::Orthogonal => {
+            if end.x - start.x >= clearance * 2. {
+                let mid_x = (start.x + end.x) * 0.5;
+                vec![
+                    start,
+                    Vec2::new(mid_x, start.y),
+                    Vec2::new(mid_x, end.y),
+                    end,
+                ]
+            } else {
+                let mid_y = (start.y + end.y) * 0.5;
+                let out_x = start.x + clearance;
+                let in_x = end.x - clearance;
+                vec![
+                    start,
+                    Vec2::new(out_x, start.y),
+                    Vec2::new(out_x, mid_y),
+                    Vec2::new(in_x, mid_y),
+                    Vec2::new(in_x, end.y),
+                    end,
+                ]
+            }
+        }
+    }
+}
+
+/// Points along `edge` in the current [`EdgeStyle`], or `None` if one of its
+/// ends doesn't exist
+pub(crate) fn path_of(
+    edge: &NodeEdge,
+    q_edge_ends: &Query<EdgeEnd>,
+    settings: &VisualiserSettings,
+) -> Option<Vec<Vec2>> {
+    let start = edge_end_port(
+        q_edge_ends.get(edge.from_entity).ok()?,
+        edge.from_channel_index,
+        true,
+        settings,
+    );
+    let end = edge_end_port(
+        q_edge_ends.get(edge.to_entity).ok()?,
+        edge.to_channel_index,
+        false,
+        settings,
+    );
+    Some(edge_path(
+        start,
+        end,
+        settings.edge_style,
+        settings.column_gap * 0.5,
+    ))
+}
+
+/// Draw the path with an arrowhead at its end, optionally dashed
+pub(crate) fn draw_edge_path(gizmos: &mut Gizmos, path: &[Vec2], color: Color, dashed: bool) {
+    if dashed {
+        // Carry the distance over between segments so that the dashes
+        // continue smoothly around corners
+        let mut travelled = 0.;
+        for segment in path.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            let length = a.distance(b);
+            let mut position = 0.;
+            while position < length {
+                let dash_index = ((travelled + position) / DASH_LENGTH) as usize;
+                let dash_end = (dash_index + 1) as f32 * DASH_LENGTH - travelled;
+                // Always make progress even when rounding puts us right on a dash boundary
+                let next = dash_end.max(position + 0.01).min(length);
+                if dash_index & 1 == 0 {
+                    gizmos.line_2d(
+                        a.lerp(b, position / length),
+                        a.lerp(b, next / length),
+                        color,
+                    );
+                }
+                position = next;
+            }
+            travelled += length;
+        }
+    } else {
+        gizmos.linestrip_2d(path.iter().copied(), color);
+    }
+    draw_arrowhead(gizmos, path, color);
+}
+
+fn draw_arrowhead(gizmos: &mut Gizmos, path: &[Vec2], color: Color) {
+    let Some(&tip) = path.last() else {
+        return;
+    };
+    // The direction of the last segment with any length
+    let Some(direction) = path
+        .iter()
+        .rev()
+        .skip(1)
+        .find_map(|point| (tip - *point).try_normalize())
+    else {
+        return;
+    };
+    for angle in [ARROW_ANGLE, -ARROW_ANGLE] {
+        let side = Vec2::from_angle(angle).rotate(-direction) * ARROW_LENGTH;
+        gizmos.line_2d(tip, tip + side, color);
+    }
+}
@@ -0,0 +1,354 @@
+//! Changing constant input values of the running graph.
+
+use std::collections::HashMap;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use knyst::{
+    controller::KnystCommands,
+    graph::{GraphId, NodeId, ParameterChange},
+    knyst,
+};
+
+use crate::{
+    cursor_over_ui, cursor_world_position, navigation::GraphView, node_height, port_at,
+    port_y_offset, GameCamera, KnystData, Node, VisualiserSettings,
+};
+
+/// The input port being edited
+#[derive(Clone, Debug)]
+pub(crate) struct EditedInput {
+    node: NodeId,
+    channel: usize,
+    /// The graph the node is in
+    graph_id: GraphId,
+    /// Node and channel name to show in the editor
+    label: String,
+}
+
+/// State of the input value editor
+#[derive(Resource, Default)]
+pub(crate) struct InputEditor {
+    input: Option<EditedInput>,
+    /// The value as it is being typed
+    text: String,
+    /// The last value sent to every input. The inspection doesn't include
+    /// input values, so this is the best guess of the current value.
+    sent: HashMap<(NodeId, usize), f32>,
+}
+
+impl InputEditor {
+    /// True while an input is being edited and key presses are text input
+    pub(crate) fn is_editing(&self) -> bool {
+        self.input.is_some()
+    }
+    fn send(&mut self, value: f32) {
+        let Some(input) = &self.input else {
+            return;
+        };
+        knyst().to_graph(input.graph_id);
+        knyst().schedule_change(ParameterChange::now(input.node.input(input.channel), value));
+        knyst().to_top_level_graph();
+        self.sent.insert((input.node, input.channel), value);
+    }
+    fn current_value(&self) -> f32 {
+        self.text.parse().ok().unwrap_or_else(|| {
+            self.input
+                .as_ref()
+                .and_then(|input| self.sent.get(&(input.node, input.channel)))
+                .copied()
+                .unwrap_or(0.)
+        })
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct EditorPanel;
+
+#[derive(Component)]
+pub(crate) struct EditorLabel;
+
+/// Shows the value, and changes it when dragged left or right
+#[derive(Component)]
+pub(crate) struct ValueField;
+
+#[derive(Component)]
+pub(crate) struct EditorCloseButton;
+
+pub(crate) fn setup_input_editor(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load(&settings.font_path),
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+    let hint_style = TextStyle {
+        font_size: 10.,
+        color: Color::GRAY,
+        ..text_style.clone()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(10.),
+                    left: Val::Px(5.),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(6.)),
+                    row_gap: Val::Px(4.),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..default()
+            },
+            Interaction::default(),
+            EditorPanel,
+        ))
+        .with_children(|panel| {
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        justify_content: JustifyContent::SpaceBetween,
+                        column_gap: Val::Px(10.),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|header| {
+                    header.spawn((
+                        TextBundle::from_section("", text_style.clone()),
+                        EditorLabel,
+                    ));
+                    header
+                        .spawn((
+                            ButtonBundle {
+                                background_color: Color::NONE.into(),
+                                ..default()
+                            },
+                            EditorCloseButton,
+                        ))
+                        .with_children(|button| {
+                            button.spawn(TextBundle::from_section("x", text_style.clone()));
+                        });
+                });
+            panel
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            min_width: Val::Px(120.),
+                            padding: UiRect::horizontal(Val::Px(4.)),
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.15, 0.15, 0.2).into(),
+                        ..default()
+                    },
+                    ValueField,
+                ))
+                .with_children(|field| {
+                    field.spawn(TextBundle::from_section("", text_style.clone()));
+                });
+            panel.spawn(TextBundle::from_section(
+                "Type a value and press Enter, or drag the value.\nShift drags finely, Esc closes.",
+                hint_style,
+            ));
+        });
+}
+
+/// Start editing an input when its port on the left edge of a node is clicked
+pub(crate) fn select_input_on_click(
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_nodes: Query<(&Node, &Transform)>,
+    q_interactions: Query<&Interaction>,
+    mut editor: ResMut<InputEditor>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || cursor_over_ui(&q_interactions) {
+        return;
+    }
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    let clicked = q_nodes.iter().find_map(|(node, transform)| {
+        port_at(
+            cursor,
+            transform.translation.xy(),
+            node_height(node.num_inputs, node.num_outputs, &settings),
+            node.num_inputs,
+            false,
+            &settings,
+        )
+        .map(|channel| (node.id, channel))
+    });
+    let Some((node, channel)) = clicked else {
+        return;
+    };
+    let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+    let label = inspection
+        .nodes
+        .iter()
+        .find(|n| n.address == node)
+        .map(|n| match n.input_channels.get(channel) {
+            Some(input) => format!("{} {}", n.name, input),
+            None => format!("{} {}", n.name, channel),
+        })
+        .unwrap_or_else(|| format!("input {channel}"));
+    editor.text = editor
+        .sent
+        .get(&(node, channel))
+        .map(|value| value.to_string())
+        .unwrap_or_default();
+    editor.input = Some(EditedInput {
+        node,
+        channel,
+        graph_id: inspection.graph_id,
+        label,
+    });
+}
+
+/// Type a new value for the edited input. Only characters that can be part of
+/// a number are taken, Enter sends the value and Escape stops editing.
+pub(crate) fn type_input_value(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut editor: ResMut<InputEditor>,
+) {
+    if !editor.is_editing() {
+        characters.clear();
+        return;
+    }
+    for event in characters.read() {
+        if event.char.is_ascii_digit() || matches!(event.char, '.' | '-' | '+' | 'e' | 'E') {
+            editor.text.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        editor.text.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) || keys.just_pressed(KeyCode::NumpadEnter) {
+        match editor.text.parse::<f32>() {
+            Ok(value) => editor.send(value),
+            Err(_) => warn!("Not a number: {}", editor.text),
+        }
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        editor.input = None;
+    }
+    // Shift is kept for fine dragging of the value
+    let typed: Vec<KeyCode> = keys
+        .get_pressed()
+        .chain(keys.get_just_released())
+        .filter(|key| !matches!(key, KeyCode::ShiftLeft | KeyCode::ShiftRight))
+        .copied()
+        .collect();
+    for key in typed {
+        keys.reset(key);
+    }
+}
+
+/// Dragging the value field changes the value by a fraction of itself per
+/// pixel, so that both small and large values can be adjusted
+pub(crate) fn drag_input_value(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_field: Query<&Interaction, With<ValueField>>,
+    mut editor: ResMut<InputEditor>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let pressed = q_field
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    let cursor = q_windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    if !pressed || !editor.is_editing() {
+        *last_cursor = None;
+        return;
+    }
+    if let (Some(cursor), Some(last)) = (cursor, *last_cursor) {
+        let delta = cursor.x - last.x;
+        if delta != 0. {
+            let value = editor.current_value();
+            let fine = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+            let speed = settings.value_drag_speed * if fine { 0.1 } else { 1. };
+            let value = value + delta * speed * value.abs().max(1.);
+            editor.text = value.to_string();
+            editor.send(value);
+        }
+    }
+    *last_cursor = cursor;
+}
+
+pub(crate) fn close_input_editor(
+    q_close: Query<&Interaction, (Changed<Interaction>, With<EditorCloseButton>)>,
+    mut editor: ResMut<InputEditor>,
+) {
+    if q_close
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        editor.input = None;
+    }
+}
+
+pub(crate) fn update_input_editor(
+    editor: Res<InputEditor>,
+    mut q_panel: Query<&mut Style, With<EditorPanel>>,
+    mut q_label: Query<&mut Text, With<EditorLabel>>,
+    q_field: Query<&Children, With<ValueField>>,
+    mut q_text: Query<&mut Text, Without<EditorLabel>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    let Ok(mut panel_style) = q_panel.get_single_mut() else {
+        return;
+    };
+    let Some(input) = &editor.input else {
+        panel_style.display = Display::None;
+        return;
+    };
+    panel_style.display = Display::Flex;
+    if let Ok(mut label) = q_label.get_single_mut() {
+        label.sections[0].value = input.label.clone();
+    }
+    for children in &q_field {
+        for child in children {
+            if let Ok(mut text) = q_text.get_mut(*child) {
+                // Show a cursor after the typed text
+                text.sections[0].value = format!("{}_", editor.text);
+            }
+        }
+    }
+}
+
+/// Mark the input port being edited
+pub(crate) fn draw_edited_input(
+    mut gizmos: Gizmos,
+    editor: Res<InputEditor>,
+    settings: Res<VisualiserSettings>,
+    q_nodes: Query<(&Node, &Transform)>,
+) {
+    let Some(input) = &editor.input else {
+        return;
+    };
+    let Some((node, transform)) = q_nodes.iter().find(|(node, _)| node.id == input.node) else {
+        return;
+    };
+    let height = node_height(node.num_inputs, node.num_outputs, &settings);
+    let port = transform.translation.xy()
+        + Vec2::new(
+            settings.node_width * -0.5,
+            port_y_offset(input.channel, height, &settings),
+        );
+    gizmos.circle_2d(port, settings.row_height * 0.4, Color::YELLOW);
+}
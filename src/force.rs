@@ -0,0 +1,315 @@
+//! Force directed layout.
+//!
+//! Edges act as springs pulling the sink of an edge to sit one column to the
+//! right of its source with the connected ports level with each other, while
+//! overlapping nodes push each other apart. Repulsion is only computed between
+//! nodes in neighbouring cells of a grid so that large graphs stay cheap.
+//!
+//! The simulation steps in the [`FixedUpdate`] schedule, 64 times per second
+//! unless the app changes [`Time<Fixed>`], so that it behaves the same at any
+//! frame rate.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    drag::Dragging, node_height, port_y_offset, GraphInputs, GraphOutputs, LayoutMode, Node,
+    NodeEdge, Pinned, VisualiserSettings,
+};
+
+/// Parameters of the force directed layout
+#[derive(Resource, Clone, Debug)]
+pub struct ForceLayoutSettings {
+    /// How much of the distance to its ideal position a spring pulls per step
+    pub spring_strength: f32,
+    /// The longest distance a single spring pulls per step
+    pub max_spring_pull: f32,
+    /// How strongly overlapping nodes push each other apart
+    pub repulsion_strength: f32,
+    /// Space to keep free around every node
+    pub margin: f32,
+    /// Fraction of the velocity kept from one step to the next
+    pub damping: f32,
+    /// Nodes never move faster than this many pixels per step
+    pub max_speed: f32,
+    /// The layout is considered stable when no node moves faster than this
+    /// many pixels per step
+    pub rest_speed: f32,
+    /// Number of steps in a row below `rest_speed` before the layout stops
+    pub rest_frames: u32,
+}
+
+impl Default for ForceLayoutSettings {
+    fn default() -> Self {
+        Self {
+            spring_strength: 0.15,
+            max_spring_pull: 50.,
+            repulsion_strength: 0.5,
+            margin: 10.,
+            damping: 0.5,
+            max_speed: 40.,
+            rest_speed: 0.05,
+            rest_frames: 30,
+        }
+    }
+}
+
+/// Whether the force directed layout has come to rest
+#[derive(Resource, Default, Debug)]
+pub struct ForceLayoutState {
+    converged: bool,
+    frames_at_rest: u32,
+}
+
+impl ForceLayoutState {
+    pub fn converged(&self) -> bool {
+        self.converged
+    }
+    /// Start moving the nodes again
+    pub fn reheat(&mut self) {
+        self.converged = false;
+        self.frames_at_rest = 0;
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Velocity(pub(crate) Vec2);
+
+pub(crate) fn not_converged(state: Res<ForceLayoutState>) -> bool {
+    !state.converged
+}
+
+/// Start the simulation again whenever the graph or the parameters change
+pub(crate) fn reheat_on_change(
+    mut state: ResMut<ForceLayoutState>,
+    q_added: Query<(), Or<(Added<Node>, Added<NodeEdge>, Added<Pinned>)>>,
+    mut removed_nodes: RemovedComponents<Node>,
+    mut removed_edges: RemovedComponents<NodeEdge>,
+    mut removed_pins: RemovedComponents<Pinned>,
+    settings: Res<VisualiserSettings>,
+    force_settings: Res<ForceLayoutSettings>,
+    layout_mode: Res<LayoutMode>,
+    dragging: Res<Dragging>,
+) {
+    let structure_changed = !q_added.is_empty()
+        || removed_nodes.read().count() > 0
+        || removed_edges.read().count() > 0
+        || removed_pins.read().count() > 0;
+    if structure_changed
+        || settings.is_changed()
+        || force_settings.is_changed()
+        || layout_mode.is_changed()
+        || dragging.is_dragging()
+    {
+        state.reheat();
+    }
+}
+
+/// Everything the simulation needs to know about a body
+struct Body {
+    entity: Entity,
+    center: Vec2,
+    size: Vec2,
+    movable: bool,
+}
+
+pub(crate) fn update_velocities(
+    mut q_velocity: Query<&mut Velocity>,
+    node_query: Query<(Entity, &Node, &Transform, Has<Pinned>)>,
+    q_graph_outputs: Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(Entity, &GraphInputs, &Transform)>,
+    edge_query: Query<&NodeEdge>,
+    settings: Res<VisualiserSettings>,
+    force_settings: Res<ForceLayoutSettings>,
+) {
+    let size =
+        |inputs, outputs| Vec2::new(settings.node_width, node_height(inputs, outputs, &settings));
+    // The graph outputs and pinned nodes stay where they are and everything
+    // else arranges itself around them
+    let bodies: Vec<Body> = node_query
+        .iter()
+        .map(|(entity, node, transform, pinned)| Body {
+            entity,
+            center: transform.translation.xy(),
+            size: size(node.num_inputs, node.num_outputs),
+            movable: !pinned,
+        })
+        .chain(q_graph_outputs.iter().map(|(entity, go, transform)| Body {
+            entity,
+            center: transform.translation.xy(),
+            size: size(go.num_outputs, go.num_outputs),
+            movable: false,
+        }))
+        .chain(q_graph_inputs.iter().map(|(entity, gi, transform)| Body {
+            entity,
+            center: transform.translation.xy(),
+            size: size(gi.num_inputs, gi.num_inputs),
+            movable: true,
+        }))
+        .collect();
+    let index: HashMap<Entity, usize> = bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| (body.entity, i))
+        .collect();
+    let mut forces = vec![Vec2::ZERO; bodies.len()];
+
+    // Springs
+    for edge in &edge_query {
+        let (Some(&from), Some(&to)) = (index.get(&edge.from_entity), index.get(&edge.to_entity))
+        else {
+            continue;
+        };
+        let ideal_offset = Vec2::new(
+            settings.column_width(),
+            port_y_offset(edge.from_channel_index, bodies[from].size.y, &settings)
+                - port_y_offset(edge.to_channel_index, bodies[to].size.y, &settings),
+        );
+        let diff = bodies[from].center + ideal_offset - bodies[to].center;
+        let pull =
+            diff.clamp_length_max(force_settings.max_spring_pull) * force_settings.spring_strength;
+        forces[to] += pull;
+        forces[from] -= pull;
+    }
+
+    // Repulsion between overlapping nodes, only looking at neighbouring grid cells
+    let cell_size = bodies
+        .iter()
+        .map(|body| body.size.max_element())
+        .fold(settings.node_width, f32::max)
+        + force_settings.margin * 2.;
+    let cell_of = |p: Vec2| (p / cell_size).floor().as_ivec2();
+    let mut grid: HashMap<IVec2, Vec<usize>> = HashMap::new();
+    for (i, body) in bodies.iter().enumerate() {
+        grid.entry(cell_of(body.center)).or_default().push(i);
+    }
+    for (i, body) in bodies.iter().enumerate() {
+        let cell = cell_of(body.center);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let Some(others) = grid.get(&(cell + IVec2::new(dx, dy))) else {
+                    continue;
+                };
+                for &j in others {
+                    if j <= i {
+                        continue;
+                    }
+                    let other = &bodies[j];
+                    let diff = body.center - other.center;
+                    let min_distance =
+                        (body.size + other.size) * 0.5 + Vec2::splat(force_settings.margin);
+                    let overlap = min_distance - diff.abs();
+                    if overlap.x <= 0. || overlap.y <= 0. {
+                        continue;
+                    }
+                    // Push apart along the axis where the least movement is needed.
+                    // `signum` is never 0 so nodes exactly on top of each other
+                    // also separate.
+                    let push = if overlap.x < overlap.y {
+                        Vec2::new(overlap.x * diff.x.signum(), 0.)
+                    } else {
+                        Vec2::new(0., overlap.y * diff.y.signum())
+                    } * force_settings.repulsion_strength;
+                    forces[i] += push;
+                    forces[j] -= push;
+                }
+            }
+        }
+    }
+
+    for (body, force) in bodies.iter().zip(forces) {
+        if let Ok(mut vel) = q_velocity.get_mut(body.entity) {
+            vel.0 = if body.movable {
+                ((vel.0 + force) * force_settings.damping)
+                    .clamp_length_max(force_settings.max_speed)
+            } else {
+                Vec2::ZERO
+            };
+        }
+    }
+}
+
+pub(crate) fn apply_velocities(
+    mut q_bodies: Query<(&mut Transform, &Velocity), Or<(With<Node>, With<GraphInputs>)>>,
+    force_settings: Res<ForceLayoutSettings>,
+    mut state: ResMut<ForceLayoutState>,
+) {
+    let mut fastest: f32 = 0.;
+    for (mut transform, vel) in q_bodies.iter_mut() {
+        transform.translation += Vec3::from((vel.0, 0.));
+        fastest = fastest.max(vel.0.length());
+    }
+    if fastest < force_settings.rest_speed {
+        state.frames_at_rest += 1;
+        if state.frames_at_rest >= force_settings.rest_frames {
+            state.converged = true;
+        }
+    } else {
+        state.frames_at_rest = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use knyst::graph::NodeId;
+
+    use super::*;
+
+    #[test]
+    fn small_graph_comes_to_rest() {
+        let mut world = World::new();
+        world.init_resource::<VisualiserSettings>();
+        world.init_resource::<ForceLayoutSettings>();
+        world.init_resource::<ForceLayoutState>();
+        let outputs = world
+            .spawn((
+                GraphOutputs {
+                    num_outputs: 1,
+                    graph_id: 0,
+                },
+                Transform::default(),
+            ))
+            .id();
+        // Three nodes on top of each other in a chain leading to the outputs
+        let mut spawn_node = |x: f32| {
+            world
+                .spawn((
+                    Node {
+                        id: NodeId::new(),
+                        num_inputs: 1,
+                        num_outputs: 1,
+                    },
+                    Transform::from_xyz(x, 0., 0.),
+                    Velocity(Vec2::ZERO),
+                ))
+                .id()
+        };
+        let nodes = [spawn_node(0.), spawn_node(1.), spawn_node(2.)];
+        for (from, to) in [
+            (nodes[0], nodes[1]),
+            (nodes[1], nodes[2]),
+            (nodes[2], outputs),
+        ] {
+            world.spawn(NodeEdge {
+                from_entity: from,
+                to_entity: to,
+                from_channel_index: 0,
+                to_channel_index: 0,
+            });
+        }
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((update_velocities, apply_velocities).chain());
+        for _ in 0..300 {
+            schedule.run(&mut world);
+        }
+
+        let rest_speed = world.resource::<ForceLayoutSettings>().rest_speed;
+        for (transform, velocity) in world.query::<(&Transform, &Velocity)>().iter(&world) {
+            assert!(transform.translation.is_finite(), "{transform:?}");
+            assert!(velocity.0.length() < rest_speed, "{:?}", velocity.0);
+        }
+        assert!(world.resource::<ForceLayoutState>().converged());
+    }
+}
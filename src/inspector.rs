@@ -0,0 +1,154 @@
+//! Side panel with the details of the selected node.
+
+use std::fmt::Write;
+
+use bevy::prelude::*;
+use knyst::inspection::{EdgeSource, NodeInspection};
+
+use crate::{
+    drag::Selected, navigation::GraphView, GraphOutputs, KnystData, Node, NodeEdge,
+    VisualiserSettings,
+};
+
+/// The inspector panel, hidden while no node is selected
+#[derive(Component)]
+pub(crate) struct Inspector;
+
+#[derive(Component)]
+pub(crate) struct InspectorText;
+
+pub(crate) fn setup_inspector(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load(&settings.font_path),
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(30.),
+                    left: Val::Px(5.),
+                    width: Val::Px(settings.inspector_width),
+                    padding: UiRect::all(Val::Px(6.)),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..default()
+            },
+            Interaction::default(),
+            Inspector,
+        ))
+        .with_children(|panel| {
+            panel.spawn((TextBundle::from_section("", text_style), InspectorText));
+        });
+}
+
+/// Name of a node together with the name of one of its channels
+fn channel_name(node: &NodeInspection, channel: usize, output: bool) -> String {
+    let channels = if output {
+        &node.output_channels
+    } else {
+        &node.input_channels
+    };
+    match channels.get(channel) {
+        Some(name) => format!("{}.{}", node.name, name),
+        None => format!("{}.{}", node.name, channel),
+    }
+}
+
+pub(crate) fn update_inspector(
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    q_selected: Query<(Entity, &Node), With<Selected>>,
+    q_nodes: Query<&Node>,
+    q_graph_outputs: Query<(), With<GraphOutputs>>,
+    q_edges: Query<&NodeEdge>,
+    mut q_panel: Query<&mut Style, With<Inspector>>,
+    mut q_text: Query<&mut Text, With<InspectorText>>,
+    mut shown: Local<String>,
+) {
+    let Ok(mut panel_style) = q_panel.get_single_mut() else {
+        return;
+    };
+    let mut selected = q_selected.iter();
+    let (Some((entity, node)), None) = (selected.next(), selected.next()) else {
+        // Only a single node can be inspected
+        if panel_style.display != Display::None {
+            panel_style.display = Display::None;
+        }
+        return;
+    };
+    let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+    let Some(node_inspection) = inspection.nodes.iter().find(|n| n.address == node.id) else {
+        return;
+    };
+
+    let mut text = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(text, "{}", node_inspection.name);
+    let _ = writeln!(text, "Address: {:?}", node_inspection.address);
+    if let Some(inner) = &node_inspection.graph_inspection {
+        let _ = writeln!(text, "Graph id: {}", inner.graph_id);
+    }
+    let _ = writeln!(text, "\nInputs:");
+    for (i, name) in node_inspection.input_channels.iter().enumerate() {
+        let _ = writeln!(text, "  {i}: {name}");
+    }
+    let _ = writeln!(text, "\nOutputs:");
+    for (i, name) in node_inspection.output_channels.iter().enumerate() {
+        let _ = writeln!(text, "  {i}: {name}");
+    }
+    let _ = writeln!(text, "\nIncoming edges:");
+    for edge in &node_inspection.input_edges {
+        let source = match edge.source {
+            EdgeSource::Node(index) => match inspection.nodes.get(index) {
+                Some(source) => channel_name(source, edge.from_index, true),
+                None => format!("node #{index}.{}", edge.from_index),
+            },
+            EdgeSource::Graph => format!("graph input {}", edge.from_index),
+        };
+        let sink = node_inspection
+            .input_channels
+            .get(edge.to_index)
+            .cloned()
+            .unwrap_or_else(|| edge.to_index.to_string());
+        let _ = writeln!(text, "  {source} -> {sink}");
+    }
+    let _ = writeln!(text, "\nOutgoing edges:");
+    for edge in q_edges.iter().filter(|edge| edge.from_entity == entity) {
+        let from = node_inspection
+            .output_channels
+            .get(edge.from_channel_index)
+            .cloned()
+            .unwrap_or_else(|| edge.from_channel_index.to_string());
+        let sink_inspection = q_nodes
+            .get(edge.to_entity)
+            .ok()
+            .and_then(|sink| inspection.nodes.iter().find(|n| n.address == sink.id));
+        let sink = if let Some(sink) = sink_inspection {
+            channel_name(sink, edge.to_channel_index, false)
+        } else if q_graph_outputs.contains(edge.to_entity) {
+            format!("graph output {}", edge.to_channel_index)
+        } else {
+            format!("? {}", edge.to_channel_index)
+        };
+        let _ = writeln!(text, "  {from} -> {sink}");
+    }
+
+    if panel_style.display != Display::Flex {
+        panel_style.display = Display::Flex;
+    }
+    if *shown != text {
+        if let Ok(mut inspector_text) = q_text.get_single_mut() {
+            inspector_text.sections[0].value = text.clone();
+        }
+        *shown = text;
+    }
+}
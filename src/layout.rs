@@ -0,0 +1,660 @@
+//! Layered (Sugiyama style) layout.
+//!
+//! The graph is split into layers counted leftwards from the graph outputs,
+//! the order within every layer is chosen to reduce edge crossings and
+//! finally every node is moved vertically towards the ports it connects to.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    animation::LayoutTarget, avoid_pinned, node_height, port_y_offset, GraphInputs, GraphOutputs,
+    Node, NodeEdge, Pinned, VisualiserSettings,
+};
+
+/// Which algorithm positions the nodes.
+///
+/// Change the resource to switch algorithm while the app is running, or press
+/// [`VisualiserSettings::layout_mode_key`] to cycle through them.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LayoutMode {
+    /// Columns grown leftwards from the graph outputs in breadth first order
+    #[default]
+    Columns,
+    /// Layered layout with crossing minimisation
+    Layered,
+    /// Nodes are pulled together by their edges and pushed apart when they
+    /// overlap, see [`ForceLayoutSettings`](crate::ForceLayoutSettings)
+    ForceDirected,
+    /// Nodes stay wherever they are
+    Manual,
+}
+
+impl LayoutMode {
+    /// The mode after this one when cycling through all of them
+    pub fn next(self) -> Self {
+        match self {
+            LayoutMode::Columns => LayoutMode::Layered,
+            LayoutMode::Layered => LayoutMode::ForceDirected,
+            LayoutMode::ForceDirected => LayoutMode::Manual,
+            LayoutMode::Manual => LayoutMode::Columns,
+        }
+    }
+}
+
+pub(crate) fn switch_layout_mode(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    mut layout_mode: ResMut<LayoutMode>,
+) {
+    if settings
+        .layout_mode_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        *layout_mode = layout_mode.next();
+        info!("Layout mode: {:?}", *layout_mode);
+    }
+}
+
+/// Number of barycenter sweeps when ordering the layers
+const ORDERING_SWEEPS: usize = 12;
+/// Number of sweeps when aligning nodes vertically with their neighbours
+const PLACEMENT_SWEEPS: usize = 8;
+
+/// A rectangle to place. Dummy vertices are inserted where an edge spans
+/// several layers and have no height.
+struct Vertex {
+    height: f32,
+    layer: usize,
+}
+
+/// An edge between two neighbouring layers. `left` is in the layer with the
+/// higher index, i.e. further away from the graph outputs.
+#[derive(Clone, Copy)]
+struct Segment {
+    left: usize,
+    left_port: usize,
+    right: usize,
+    right_port: usize,
+}
+
+/// An edge from the output `from_port` of `from` to the input `to_port` of `to`
+pub(crate) struct LayoutEdge {
+    pub(crate) from: usize,
+    pub(crate) from_port: usize,
+    pub(crate) to: usize,
+    pub(crate) to_port: usize,
+}
+
+/// Compute positions for `heights.len()` vertices relative to the position of
+/// the `sink` vertex. If there is a `source` vertex it is put in a layer of
+/// its own furthest to the left.
+pub(crate) fn layered_positions(
+    heights: &[f32],
+    edges: &[LayoutEdge],
+    sink: usize,
+    source: Option<usize>,
+    settings: &VisualiserSettings,
+) -> Vec<Vec2> {
+    let num_real = heights.len();
+    let dag = remove_cycles(num_real, edges);
+    let layers_of = assign_layers(num_real, &dag, sink, source);
+
+    let mut vertices: Vec<Vertex> = heights
+        .iter()
+        .zip(&layers_of)
+        .map(|(height, layer)| Vertex {
+            height: *height,
+            layer: *layer,
+        })
+        .collect();
+    // Split edges spanning several layers into chains through dummy vertices
+    let mut segments = vec![];
+    for edge in &dag {
+        let mut right = edge.right;
+        let mut right_port = edge.right_port;
+        for layer in vertices[edge.right].layer + 1..vertices[edge.left].layer {
+            vertices.push(Vertex { height: 0., layer });
+            let dummy = vertices.len() - 1;
+            segments.push(Segment {
+                left: dummy,
+                left_port: 0,
+                right,
+                right_port,
+            });
+            right = dummy;
+            right_port = 0;
+        }
+        segments.push(Segment {
+            left: edge.left,
+            left_port: edge.left_port,
+            right,
+            right_port,
+        });
+    }
+
+    let mut layers = initial_order(&vertices, &segments, sink);
+    reduce_crossings(&mut layers, &vertices, &segments, settings);
+    let tops = place_vertically(&layers, &vertices, &segments, sink, settings);
+
+    let sink_center = tops[sink] + vertices[sink].height * 0.5;
+    (0..num_real)
+        .map(|v| {
+            let center = tops[v] + vertices[v].height * 0.5;
+            Vec2::new(
+                -(vertices[v].layer as f32) * settings.column_width(),
+                -(center - sink_center),
+            )
+        })
+        .collect()
+}
+
+/// Reverse the edges that close a cycle so that the rest of the algorithm can
+/// work on a directed acyclic graph. Self loops are dropped.
+fn remove_cycles(num_vertices: usize, edges: &[LayoutEdge]) -> Vec<Segment> {
+    let mut outgoing = vec![vec![]; num_vertices];
+    for (i, edge) in edges.iter().enumerate() {
+        if edge.from != edge.to {
+            outgoing[edge.from].push(i);
+        }
+    }
+    // 0: unvisited, 1: on the stack, 2: done
+    let mut state = vec![0u8; num_vertices];
+    let mut reversed = vec![false; edges.len()];
+    for start in 0..num_vertices {
+        if state[start] != 0 {
+            continue;
+        }
+        // Iterative DFS to not overflow the stack on long chains
+        let mut stack = vec![(start, 0)];
+        state[start] = 1;
+        while let Some((v, next)) = stack.last_mut() {
+            if let Some(&edge_index) = outgoing[*v].get(*next) {
+                *next += 1;
+                let to = edges[edge_index].to;
+                match state[to] {
+                    0 => {
+                        state[to] = 1;
+                        stack.push((to, 0));
+                    }
+                    1 => reversed[edge_index] = true,
+                    _ => (),
+                }
+            } else {
+                state[*v] = 2;
+                stack.pop();
+            }
+        }
+    }
+    edges
+        .iter()
+        .zip(reversed)
+        .filter(|(edge, _)| edge.from != edge.to)
+        .map(|(edge, reversed)| {
+            if reversed {
+                Segment {
+                    left: edge.to,
+                    left_port: edge.to_port,
+                    right: edge.from,
+                    right_port: edge.from_port,
+                }
+            } else {
+                Segment {
+                    left: edge.from,
+                    left_port: edge.from_port,
+                    right: edge.to,
+                    right_port: edge.to_port,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Longest path layering counted from the sink. Vertices that don't lead
+/// anywhere are put in the layer right before the sink.
+fn assign_layers(
+    num_vertices: usize,
+    dag: &[Segment],
+    sink: usize,
+    source: Option<usize>,
+) -> Vec<usize> {
+    let mut outgoing = vec![vec![]; num_vertices];
+    for segment in dag {
+        outgoing[segment.left].push(segment.right);
+    }
+    let mut layers: Vec<Option<usize>> = vec![None; num_vertices];
+    layers[sink] = Some(0);
+    for start in 0..num_vertices {
+        // Iterative post order DFS, the graph is acyclic at this point
+        let mut stack = vec![start];
+        while let Some(&v) = stack.last() {
+            if layers[v].is_some() {
+                stack.pop();
+                continue;
+            }
+            let unresolved: Vec<usize> = outgoing[v]
+                .iter()
+                .copied()
+                .filter(|to| layers[*to].is_none())
+                .collect();
+            if unresolved.is_empty() {
+                let layer = outgoing[v]
+                    .iter()
+                    .filter_map(|to| layers[*to])
+                    .max()
+                    .map_or(1, |max| max + 1);
+                layers[v] = Some(layer);
+                stack.pop();
+            } else {
+                stack.extend(unresolved);
+            }
+        }
+    }
+    let mut layers: Vec<usize> = layers.into_iter().map(|l| l.unwrap_or(1)).collect();
+    if let Some(source) = source {
+        let max_layer = layers
+            .iter()
+            .enumerate()
+            .filter(|(v, _)| *v != source)
+            .map(|(_, l)| *l)
+            .max()
+            .unwrap_or(0);
+        layers[source] = max_layer + 1;
+    }
+    layers
+}
+
+/// Order every layer by breadth first search from the sink so that the
+/// initial order is already somewhat sensible.
+fn initial_order(vertices: &[Vertex], segments: &[Segment], sink: usize) -> Vec<Vec<usize>> {
+    let num_layers = vertices.iter().map(|v| v.layer).max().unwrap_or(0) + 1;
+    let mut layers = vec![vec![]; num_layers];
+    let mut visited = vec![false; vertices.len()];
+    let mut incoming = vec![vec![]; vertices.len()];
+    for segment in segments {
+        incoming[segment.right].push((segment.right_port, segment.left));
+    }
+    for list in &mut incoming {
+        list.sort_by_key(|(port, _)| *port);
+    }
+    let mut queue = std::collections::VecDeque::from([sink]);
+    visited[sink] = true;
+    let mut visit_order = vec![];
+    loop {
+        while let Some(v) = queue.pop_front() {
+            visit_order.push(v);
+            for (_, left) in &incoming[v] {
+                if !visited[*left] {
+                    visited[*left] = true;
+                    queue.push_back(*left);
+                }
+            }
+        }
+        // Parts of the graph that don't reach the sink
+        match visited.iter().position(|visited| !visited) {
+            Some(v) => {
+                visited[v] = true;
+                queue.push_back(v);
+            }
+            None => break,
+        }
+    }
+    for v in visit_order {
+        layers[vertices[v].layer].push(v);
+    }
+    layers
+}
+
+/// Top edge of every vertex when the layers are simply stacked in order
+fn stacked_tops(
+    layers: &[Vec<usize>],
+    vertices: &[Vertex],
+    settings: &VisualiserSettings,
+) -> Vec<f32> {
+    let mut tops = vec![0.; vertices.len()];
+    for layer in layers {
+        let mut y = 0.;
+        for v in layer {
+            tops[*v] = y;
+            y += vertices[*v].height + settings.row_gap;
+        }
+    }
+    tops
+}
+
+/// Distance from the top of a vertex down to one of its ports
+fn port_offset(vertex: &Vertex, port: usize, settings: &VisualiserSettings) -> f32 {
+    if vertex.height == 0. {
+        0.
+    } else {
+        vertex.height * 0.5 - port_y_offset(port, vertex.height, settings)
+    }
+}
+
+/// Sort the layers by the barycenter of the ports they connect to, sweeping
+/// back and forth and keeping the order with the fewest crossings.
+fn reduce_crossings(
+    layers: &mut [Vec<usize>],
+    vertices: &[Vertex],
+    segments: &[Segment],
+    settings: &VisualiserSettings,
+) {
+    let mut best = layers.to_vec();
+    let mut best_crossings = count_crossings(layers, vertices, segments, settings);
+    for sweep in 0..ORDERING_SWEEPS {
+        let towards_left = sweep % 2 == 0;
+        let layer_indices: Vec<usize> = if towards_left {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+        for layer in layer_indices {
+            let tops = stacked_tops(layers, vertices, settings);
+            let mut barycenters: HashMap<usize, (f32, usize)> = HashMap::new();
+            for segment in segments {
+                // Look at the neighbours in the layer that was just fixed
+                let (own, own_port, other, other_port) = if towards_left {
+                    (
+                        segment.left,
+                        segment.left_port,
+                        segment.right,
+                        segment.right_port,
+                    )
+                } else {
+                    (
+                        segment.right,
+                        segment.right_port,
+                        segment.left,
+                        segment.left_port,
+                    )
+                };
+                if vertices[own].layer != layer {
+                    continue;
+                }
+                let target = tops[other] + port_offset(&vertices[other], other_port, settings)
+                    - port_offset(&vertices[own], own_port, settings);
+                let entry = barycenters.entry(own).or_insert((0., 0));
+                entry.0 += target;
+                entry.1 += 1;
+            }
+            let key = |v: &usize| match barycenters.get(v) {
+                Some((sum, count)) => sum / *count as f32,
+                None => tops[*v],
+            };
+            layers[layer].sort_by(|a, b| key(a).total_cmp(&key(b)));
+        }
+        let crossings = count_crossings(layers, vertices, segments, settings);
+        if crossings < best_crossings {
+            best_crossings = crossings;
+            best = layers.to_vec();
+        }
+    }
+    layers.clone_from_slice(&best);
+}
+
+/// Number of pairs of segments between the same layers that cross
+fn count_crossings(
+    layers: &[Vec<usize>],
+    vertices: &[Vertex],
+    segments: &[Segment],
+    settings: &VisualiserSettings,
+) -> usize {
+    let tops = stacked_tops(layers, vertices, settings);
+    let port_y = |v: usize, port: usize| tops[v] + port_offset(&vertices[v], port, settings);
+    let mut by_layer: HashMap<usize, Vec<(f32, f32)>> = HashMap::new();
+    for segment in segments {
+        by_layer
+            .entry(vertices[segment.right].layer)
+            .or_default()
+            .push((
+                port_y(segment.left, segment.left_port),
+                port_y(segment.right, segment.right_port),
+            ));
+    }
+    let mut crossings = 0;
+    for segments in by_layer.values() {
+        for (i, a) in segments.iter().enumerate() {
+            for b in &segments[i + 1..] {
+                if (a.0 - b.0) * (a.1 - b.1) < 0. {
+                    crossings += 1;
+                }
+            }
+        }
+    }
+    crossings
+}
+
+/// Move vertices towards the ports they are connected to while keeping the
+/// order within every layer and the gaps between vertices. Returns the top
+/// edge of every vertex.
+fn place_vertically(
+    layers: &[Vec<usize>],
+    vertices: &[Vertex],
+    segments: &[Segment],
+    sink: usize,
+    settings: &VisualiserSettings,
+) -> Vec<f32> {
+    let mut tops = stacked_tops(layers, vertices, settings);
+    for sweep in 0..PLACEMENT_SWEEPS {
+        let towards_left = sweep % 2 == 0;
+        let layer_indices: Vec<usize> = if towards_left {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+        for layer in layer_indices {
+            let mut targets: HashMap<usize, (f32, usize)> = HashMap::new();
+            for segment in segments {
+                for (own, own_port, other, other_port) in [
+                    (
+                        segment.left,
+                        segment.left_port,
+                        segment.right,
+                        segment.right_port,
+                    ),
+                    (
+                        segment.right,
+                        segment.right_port,
+                        segment.left,
+                        segment.left_port,
+                    ),
+                ] {
+                    if vertices[own].layer != layer || own == sink {
+                        continue;
+                    }
+                    let target = tops[other] + port_offset(&vertices[other], other_port, settings)
+                        - port_offset(&vertices[own], own_port, settings);
+                    let entry = targets.entry(own).or_insert((0., 0));
+                    entry.0 += target;
+                    entry.1 += 1;
+                }
+            }
+            let desired: Vec<f32> = layers[layer]
+                .iter()
+                .map(|v| match targets.get(v) {
+                    Some((sum, count)) => sum / *count as f32,
+                    None => tops[*v],
+                })
+                .collect();
+            let placed = resolve_overlaps(&layers[layer], &desired, vertices, settings);
+            for (v, top) in layers[layer].iter().zip(placed) {
+                tops[*v] = top;
+            }
+        }
+    }
+    tops
+}
+
+/// Find positions as close to `desired` as possible without overlaps, by
+/// averaging a downwards and an upwards packing of the layer.
+fn resolve_overlaps(
+    layer: &[usize],
+    desired: &[f32],
+    vertices: &[Vertex],
+    settings: &VisualiserSettings,
+) -> Vec<f32> {
+    let gap = settings.row_gap;
+    let mut down = desired.to_vec();
+    for i in 1..layer.len() {
+        let min = down[i - 1] + vertices[layer[i - 1]].height + gap;
+        down[i] = down[i].max(min);
+    }
+    let mut up = desired.to_vec();
+    for i in (0..layer.len().saturating_sub(1)).rev() {
+        let max = up[i + 1] - vertices[layer[i]].height - gap;
+        up[i] = up[i].min(max);
+    }
+    let mut placed: Vec<f32> = down.iter().zip(&up).map(|(d, u)| (d + u) * 0.5).collect();
+    for i in 1..layer.len() {
+        let min = placed[i - 1] + vertices[layer[i - 1]].height + gap;
+        placed[i] = placed[i].max(min);
+    }
+    placed
+}
+
+/// Position all nodes using [`layered_positions`]. The result is cached and
+/// only recomputed when nodes or edges are added or removed.
+pub(crate) fn layered_layout(
+    mut node_query: Query<
+        (Entity, &Node, &Transform, &mut LayoutTarget, Has<Pinned>),
+        Without<GraphOutputs>,
+    >,
+    q_graph_outputs: Query<(Entity, &Transform, &GraphOutputs)>,
+    mut q_graph_inputs: Query<
+        (Entity, &Transform, &mut LayoutTarget, &GraphInputs),
+        (Without<Node>, Without<GraphOutputs>),
+    >,
+    edge_query: Query<&NodeEdge>,
+    q_added: Query<
+        (),
+        Or<(
+            Added<Node>,
+            Added<NodeEdge>,
+            Added<GraphOutputs>,
+            Added<GraphInputs>,
+        )>,
+    >,
+    mut removed_nodes: RemovedComponents<Node>,
+    mut removed_edges: RemovedComponents<NodeEdge>,
+    settings: Res<VisualiserSettings>,
+    mut cached: Local<HashMap<Entity, Vec2>>,
+) {
+    let Ok((go_entity, go_transform, go)) = q_graph_outputs.get_single() else {
+        return;
+    };
+    let structure_changed =
+        !q_added.is_empty() || removed_nodes.read().count() > 0 || removed_edges.read().count() > 0;
+    if structure_changed || settings.is_changed() || cached.is_empty() {
+        let mut entities = vec![go_entity];
+        let mut heights = vec![node_height(go.num_outputs, go.num_outputs, &settings)];
+        let mut source = None;
+        for (entity, node, ..) in &node_query {
+            entities.push(entity);
+            heights.push(node_height(node.num_inputs, node.num_outputs, &settings));
+        }
+        for (entity, _, _, gi) in &q_graph_inputs {
+            source = Some(entities.len());
+            entities.push(entity);
+            heights.push(node_height(gi.num_inputs, gi.num_inputs, &settings));
+        }
+        let index: HashMap<Entity, usize> =
+            entities.iter().enumerate().map(|(i, e)| (*e, i)).collect();
+        let edges: Vec<LayoutEdge> = edge_query
+            .iter()
+            .filter_map(|edge| {
+                Some(LayoutEdge {
+                    from: *index.get(&edge.from_entity)?,
+                    from_port: edge.from_channel_index,
+                    to: *index.get(&edge.to_entity)?,
+                    to_port: edge.to_channel_index,
+                })
+            })
+            .collect();
+        let positions = layered_positions(&heights, &edges, 0, source, &settings);
+        *cached = entities.into_iter().zip(positions).collect();
+    }
+    let origin = go_transform.translation.xy();
+    let mut heights = HashMap::new();
+    let mut pinned = vec![];
+    let mut plan = HashMap::new();
+    for (entity, node, transform, _, is_pinned) in &node_query {
+        let height = node_height(node.num_inputs, node.num_outputs, &settings);
+        heights.insert(entity, height);
+        if is_pinned {
+            pinned.push(Rect::from_center_size(
+                transform.translation.xy(),
+                Vec2::new(settings.node_width, height),
+            ));
+        } else if let Some(position) = cached.get(&entity) {
+            plan.insert(entity, origin + *position);
+        }
+    }
+    avoid_pinned(&mut plan, &heights, &pinned, &settings);
+    for (entity, _, transform, mut target, _) in &mut node_query {
+        if let Some(position) = plan.get(&entity) {
+            target.set(*position, transform.translation.xy());
+        }
+    }
+    for (entity, transform, mut target, _) in &mut q_graph_inputs {
+        if let Some(position) = cached.get(&entity) {
+            target.set(origin + *position, transform.translation.xy());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(from: usize, to: usize) -> LayoutEdge {
+        LayoutEdge {
+            from,
+            from_port: 0,
+            to,
+            to_port: 0,
+        }
+    }
+
+    #[test]
+    fn barycenter_sweeps_remove_crossing() {
+        let settings = VisualiserSettings::default();
+        let vertices: Vec<Vertex> = [1, 1, 0, 0]
+            .into_iter()
+            .map(|layer| Vertex { height: 30., layer })
+            .collect();
+        // The upper vertex on the left connects to the lower one on the right
+        // and the other way around
+        let segments = remove_cycles(4, &[edge(0, 3), edge(1, 2)]);
+        let mut layers = vec![vec![2, 3], vec![0, 1]];
+        assert_eq!(count_crossings(&layers, &vertices, &segments, &settings), 1);
+        reduce_crossings(&mut layers, &vertices, &segments, &settings);
+        assert_eq!(count_crossings(&layers, &vertices, &segments, &settings), 0);
+    }
+
+    #[test]
+    fn chain_gets_one_layer_per_vertex() {
+        let dag = remove_cycles(5, &[edge(0, 1), edge(1, 2), edge(2, 3)]);
+        let layers = assign_layers(5, &dag, 3, None);
+        // The unconnected vertex goes right before the sink
+        assert_eq!(layers, vec![3, 2, 1, 0, 1]);
+        let layers = assign_layers(5, &dag, 3, Some(4));
+        assert_eq!(layers, vec![3, 2, 1, 0, 4]);
+    }
+
+    #[test]
+    fn cycles_are_broken() {
+        let edges = [edge(0, 1), edge(1, 2), edge(2, 1), edge(1, 1), edge(2, 3)];
+        let dag = remove_cycles(4, &edges);
+        // The self loop is dropped
+        assert_eq!(dag.len(), 4);
+        let layers = assign_layers(4, &dag, 3, None);
+        for segment in &dag {
+            assert!(layers[segment.left] > layers[segment.right]);
+        }
+        let positions =
+            layered_positions(&[30.; 4], &edges, 3, None, &VisualiserSettings::default());
+        assert_eq!(positions.len(), 4);
+        assert!(positions.iter().all(|p| p.is_finite()));
+    }
+}
@@ -1,48 +1,326 @@
-use std::sync::mpsc::Receiver;
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
-use bevy::{core::Zeroable, prelude::*, window::PrimaryWindow};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        mpsc::{Receiver, TryRecvError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{ecs::query::ROQueryItem, prelude::*, window::PrimaryWindow};
 use knyst::{controller::KnystCommands, graph::NodeId, inspection::GraphInspection, knyst};
 use rand::{thread_rng, Rng};
 
+mod animation;
+mod camera;
+mod capture;
+mod drag;
+mod edges;
+mod editing;
+mod force;
+mod inspector;
+mod layout;
+mod metering;
+mod minimap;
+mod navigation;
+mod palette;
+mod scope;
+mod settings;
+mod spectrum;
+mod wiring;
+use animation::LayoutTarget;
+pub use camera::{CameraCommand, GameCamera};
+pub use capture::{MockSampleCapture, SampleCapture, SampleCaptures};
+pub use drag::{Pinned, Selected};
+pub use edges::EdgeStyle;
+use force::Velocity;
+pub use force::{ForceLayoutSettings, ForceLayoutState};
+pub use layout::LayoutMode;
+pub use metering::{Level, LevelMeters, LevelTap, MockLevelTap};
+use navigation::GraphView;
+pub use palette::PaletteEntry;
+pub use settings::VisualiserSettings;
+pub use wiring::SelectedEdge;
+
+/// Bevy plugin that inspects the running knyst graph and draws it.
+///
+/// Add it next to your own plugins to embed the visualiser in an existing
+/// Bevy application. [`DefaultPlugins`] (or an equivalent set providing
+/// windowing, rendering, text and gizmos) need to be added by the host app.
+#[derive(Default)]
+pub struct KnystVisualiserPlugin {
+    settings: VisualiserSettings,
+    layout_mode: LayoutMode,
+    level_meters: Option<LevelMeters>,
+    sample_captures: Option<SampleCaptures>,
+    palette_entries: Vec<PaletteEntry>,
+}
+
+impl KnystVisualiserPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_settings(mut self, settings: VisualiserSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+    /// The layout algorithm to start with
+    pub fn with_layout_mode(mut self, layout_mode: LayoutMode) -> Self {
+        self.layout_mode = layout_mode;
+        self
+    }
+    /// Show live output levels of the nodes, measured by `tap`
+    pub fn with_level_tap(mut self, tap: impl LevelTap) -> Self {
+        self.level_meters = Some(LevelMeters(Arc::new(tap)));
+        self
+    }
+    /// Allow clicking output ports to look at their signal, recorded by `capture`
+    pub fn with_sample_capture(mut self, capture: impl SampleCapture) -> Self {
+        self.sample_captures = Some(SampleCaptures(Arc::new(capture)));
+        self
+    }
+    /// Make another kind of node available in the node palette
+    pub fn with_palette_entry(mut self, entry: PaletteEntry) -> Self {
+        self.palette_entries.push(entry);
+        self
+    }
+    /// Open a window configured from the settings and block until it is closed.
+    pub fn run(self) {
+        let window = Window {
+            title: self.settings.window_title.clone(),
+            resolution: (self.settings.window_size.x, self.settings.window_size.y).into(),
+            ..default()
+        };
+        App::new()
+            .add_plugins((
+                DefaultPlugins.set(WindowPlugin {
+                    primary_window: Some(window),
+                    ..default()
+                }),
+                self,
+            ))
+            .run();
+    }
+}
+
+impl Plugin for KnystVisualiserPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .insert_non_send_resource(KnystData::new(&self.settings))
+            .add_event::<RequestInspection>()
+            .add_event::<InspectionUpdated>()
+            .init_resource::<GraphView>()
+            .add_systems(Startup, navigation::setup_breadcrumbs)
+            .add_systems(
+                Update,
+                (
+                    navigation::enter_graph_on_double_click,
+                    navigation::leave_graph,
+                    navigation::update_breadcrumbs,
+                )
+                    .chain()
+                    .before(update_inspection),
+            )
+            .add_systems(Startup, setup)
+            .add_systems(Update, update_inspection)
+            .add_systems(
+                Update,
+                (mark_feedback_edges, draw_edges)
+                    .chain()
+                    .after(update_inspection),
+            )
+            .insert_resource(self.layout_mode)
+            .init_resource::<drag::Dragging>()
+            .add_systems(
+                Update,
+                (
+                    drag::drag_nodes,
+                    drag::unpin_nodes,
+                    drag::draw_pins,
+                    drag::draw_selection,
+                ),
+            )
+            .add_systems(Update, layout::switch_layout_mode)
+            .add_systems(
+                Update,
+                move_nodes.run_if(resource_equals(LayoutMode::Columns)),
+            )
+            .add_systems(
+                Update,
+                layout::layered_layout.run_if(resource_equals(LayoutMode::Layered)),
+            )
+            .add_systems(
+                Update,
+                animation::move_towards_targets
+                    .after(move_nodes)
+                    .after(layout::layered_layout)
+                    .run_if(
+                        resource_equals(LayoutMode::Columns)
+                            .or_else(resource_equals(LayoutMode::Layered)),
+                    ),
+            )
+            .init_resource::<ForceLayoutSettings>()
+            .init_resource::<ForceLayoutState>()
+            .add_systems(
+                Update,
+                force::reheat_on_change.run_if(resource_equals(LayoutMode::ForceDirected)),
+            )
+            // A fixed timestep so that the layout settles the same way at any
+            // frame rate
+            .add_systems(
+                FixedUpdate,
+                (force::update_velocities, force::apply_velocities)
+                    .chain()
+                    .run_if(resource_equals(LayoutMode::ForceDirected))
+                    .run_if(force::not_converged),
+            )
+            .add_systems(
+                Update,
+                (
+                    camera::zoom_camera,
+                    camera::drag_pan_camera,
+                    camera::keyboard_pan_camera,
+                    camera::edge_scroll_camera,
+                ),
+            )
+            .add_event::<CameraCommand>()
+            .init_resource::<camera::CameraTransition>()
+            .add_systems(
+                Update,
+                (
+                    camera::send_camera_commands,
+                    camera::handle_camera_commands,
+                    camera::animate_camera,
+                )
+                    .chain()
+                    .after(camera::zoom_camera)
+                    .after(camera::drag_pan_camera)
+                    .after(camera::keyboard_pan_camera)
+                    .after(camera::edge_scroll_camera),
+            )
+            .add_systems(
+                Update,
+                metering::update_level_meters.run_if(resource_exists::<LevelMeters>()),
+            )
+            .init_resource::<capture::Probe>()
+            .init_resource::<spectrum::SpectrumState>()
+            .add_systems(Startup, capture::setup_probe_panels)
+            .add_systems(
+                Update,
+                (
+                    capture::probe_output_on_click,
+                    capture::update_capture,
+                    capture::draw_probe,
+                    scope::close_scope,
+                    scope::update_scope,
+                    spectrum::close_spectrum,
+                    spectrum::update_spectrum,
+                )
+                    .chain()
+                    .run_if(resource_exists::<SampleCaptures>()),
+            )
+            .init_resource::<editing::InputEditor>()
+            .add_systems(Startup, editing::setup_input_editor)
+            .add_systems(
+                Update,
+                (
+                    editing::select_input_on_click,
+                    editing::drag_input_value,
+                    editing::close_input_editor,
+                    editing::update_input_editor,
+                    editing::draw_edited_input,
+                )
+                    .chain(),
+            )
+            .init_resource::<wiring::ConnectionDrag>()
+            .add_systems(
+                Update,
+                (wiring::start_connection_drag, wiring::select_edge_on_click)
+                    .chain()
+                    .before(drag::drag_nodes),
+            )
+            .add_systems(
+                Update,
+                (
+                    wiring::finish_connection_drag,
+                    wiring::disconnect_selected_edges,
+                    wiring::draw_connection_drag,
+                    wiring::draw_selected_edges,
+                )
+                    .chain()
+                    .after(drag::drag_nodes)
+                    .before(update_inspection),
+            )
+            .add_systems(Startup, inspector::setup_inspector)
+            .add_systems(Update, inspector::update_inspector.after(drag::drag_nodes))
+            .add_systems(Startup, minimap::setup_minimap)
+            .add_systems(
+                Update,
+                (
+                    minimap::toggle_minimap,
+                    minimap::update_minimap,
+                    minimap::click_minimap,
+                )
+                    .chain()
+                    .after(camera::animate_camera),
+            )
+            .insert_resource(palette::PaletteEntries(
+                palette::default_entries()
+                    .into_iter()
+                    .chain(self.palette_entries.iter().cloned())
+                    .collect(),
+            ))
+            .init_resource::<palette::NodePalette>()
+            .init_resource::<palette::Placements>()
+            .add_event::<palette::ChosenEntry>()
+            .add_systems(Startup, palette::setup_palette)
+            // Typed text is taken from the key input right after it is read,
+            // so that typing a value or a search doesn't trigger shortcuts
+            .add_systems(
+                PreUpdate,
+                (editing::type_input_value, palette::type_palette_query)
+                    .after(bevy::input::InputSystem),
+            )
+            .add_systems(
+                Update,
+                (
+                    palette::click_palette,
+                    palette::update_palette,
+                    palette::add_chosen_nodes,
+                )
+                    .chain()
+                    .before(update_inspection),
+            );
+        if let Some(level_meters) = &self.level_meters {
+            app.insert_resource(level_meters.clone());
+        }
+        if let Some(sample_captures) = &self.sample_captures {
+            app.insert_resource(sample_captures.clone());
+        }
+    }
+}
+
+/// Open a window with the visualiser and block until it is closed.
 pub fn init_knyst_visualiser() {
-    println!("Hello, world!");
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_non_send_resource(KnystData::new())
-        .add_systems(Startup, setup)
-        .add_systems(Update, update_inspection)
-        .add_systems(Update, draw_edges)
-        .add_systems(Update, move_nodes)
-        // .add_systems(Update, update_velocities)
-        .add_systems(Update, apply_velocities)
-        .add_systems(Update, move_camera_mouse)
-        .run();
-}
-
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let font = asset_server.load("fonts/Terminess (TTF) Bold Nerd Font Complete.ttf");
-    let text_style = TextStyle {
-        font: font.clone(),
-        font_size: 30.0,
-        color: Color::WHITE,
-    };
-    let text_alignment = TextAlignment::Center;
-    // 2d camera
-    commands.spawn((Camera2dBundle::default(), GameCamera));
+    KnystVisualiserPlugin::new().run();
 }
 
-#[derive(Component)]
-struct GameCamera;
+fn setup(mut commands: Commands) {
+    // 2d camera
+    commands.spawn((Camera2dBundle::default(), GameCamera::default()));
+}
 
 #[derive(Component)]
 struct Node {
     id: NodeId,
     num_inputs: usize,
     num_outputs: usize,
-    edge_acceleration: f32,
 }
+/// A node that is a graph of its own
 #[derive(Component)]
-struct Graph(u64);
+struct Graph;
 
 #[derive(Component)]
 struct GraphOutputs {
@@ -50,6 +328,56 @@ struct GraphOutputs {
     graph_id: u64,
 }
 
+#[derive(Component)]
+struct GraphInputs {
+    num_inputs: usize,
+    graph_id: u64,
+}
+
+/// One end of an edge, identified the way the inspection identifies it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EdgeEndpoint {
+    Node(NodeId),
+    GraphOutputs,
+    GraphInputs,
+}
+
+/// Uniquely identifies an edge: (source, from_index, sink, to_index)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct EdgeKey {
+    source: EdgeEndpoint,
+    from_index: usize,
+    sink: EdgeEndpoint,
+    to_index: usize,
+}
+
+/// Anything an edge can start or end at
+type EdgeEnd = (
+    &'static Transform,
+    AnyOf<(&'static Node, &'static GraphOutputs, &'static GraphInputs)>,
+);
+
+/// Where edges attach to input or output `index` of an edge end
+fn edge_end_port(
+    (transform, (node, graph_outputs, graph_inputs)): ROQueryItem<EdgeEnd>,
+    index: usize,
+    output: bool,
+    settings: &VisualiserSettings,
+) -> Vec2 {
+    let height = match (node, graph_outputs, graph_inputs) {
+        (Some(node), ..) => node_height(node.num_inputs, node.num_outputs, settings),
+        (_, Some(go), _) => node_height(go.num_outputs, go.num_outputs, settings),
+        (_, _, Some(gi)) => node_height(gi.num_inputs, gi.num_inputs, settings),
+        _ => settings.row_height,
+    };
+    let x = if output {
+        settings.node_width * 0.5
+    } else {
+        settings.node_width * -0.5
+    };
+    transform.translation.xy() + Vec2::new(x, port_y_offset(index, height, settings))
+}
+
 #[derive(Component)]
 struct NodeEdge {
     from_entity: Entity,
@@ -57,45 +385,174 @@ struct NodeEdge {
     from_channel_index: usize,
     to_channel_index: usize,
 }
-#[derive(Component)]
-struct Velocity(Vec2);
+
+/// Send this event to request a new inspection of the graph right away instead
+/// of waiting for [`VisualiserSettings::reinspection_interval`] to pass.
+#[derive(Event, Default)]
+pub struct RequestInspection;
+
+/// Sent when a new inspection has arrived from knyst
+#[derive(Event)]
+struct InspectionUpdated;
 
 struct KnystData {
     latest_inspection: GraphInspection,
     next_receiver: Option<Receiver<GraphInspection>>,
+    poll_timer: Timer,
+    /// [`Time::elapsed`] when the last inspection was requested
+    last_request: Option<Duration>,
+    /// Set when the entities need to be rebuilt from the latest inspection
+    /// even though no new inspection has arrived
+    view_dirty: bool,
 }
 impl KnystData {
-    fn new() -> Self {
+    fn new(settings: &VisualiserSettings) -> Self {
         Self {
             latest_inspection: GraphInspection::empty(),
             next_receiver: None,
+            poll_timer: Timer::new(settings.inspection_poll_interval, TimerMode::Repeating),
+            last_request: None,
+            view_dirty: false,
         }
     }
 }
 
-fn node_height(num_inputs: usize, num_outputs: usize) -> f32 {
-    15. * num_inputs.max(num_outputs).max(1) as f32
+fn node_height(num_inputs: usize, num_outputs: usize, settings: &VisualiserSettings) -> f32 {
+    settings.row_height * num_inputs.max(num_outputs).max(1) as f32
+}
+
+/// Vertical offset of an input or output channel from the center of its node,
+/// counting rows down from the top of the node
+fn port_y_offset(index: usize, node_height: f32, settings: &VisualiserSettings) -> f32 {
+    node_height * 0.5 - (index as f32 + 0.5) * settings.row_height
+}
+
+/// How far from the edge of a node a port can be clicked, in pixels
+const PORT_HIT_WIDTH: f32 = 12.;
+
+/// The input (on the left edge) or output (on the right edge) port at
+/// `position` of a node centered at `center`, if any
+fn port_at(
+    position: Vec2,
+    center: Vec2,
+    height: f32,
+    num_ports: usize,
+    output: bool,
+    settings: &VisualiserSettings,
+) -> Option<usize> {
+    let half_width = settings.node_width * 0.5;
+    let edge = if output {
+        center.x + half_width
+    } else {
+        center.x - half_width
+    };
+    if (position.x - edge).abs() > PORT_HIT_WIDTH * 0.5 {
+        return None;
+    }
+    (0..num_ports).find(|&channel| {
+        (position.y - (center.y + port_y_offset(channel, height, settings))).abs()
+            <= settings.row_height * 0.5
+    })
+}
+
+fn node_size(node: &Node, settings: &VisualiserSettings) -> Vec2 {
+    Vec2::new(
+        settings.node_width,
+        node_height(node.num_inputs, node.num_outputs, settings),
+    )
+}
+
+/// The position of the cursor in world coordinates, if it is inside the window
+fn cursor_world_position(
+    q_windows: &Query<&Window, With<PrimaryWindow>>,
+    q_camera: &Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+) -> Option<Vec2> {
+    let (camera, camera_transform) = q_camera.get_single().ok()?;
+    let cursor = q_windows.get_single().ok()?.cursor_position()?;
+    camera.viewport_to_world_2d(camera_transform, cursor)
+}
+
+/// Insert `component` unless `entity` has been despawned by the time the
+/// commands are applied, for example by a new inspection
+fn try_insert(commands: &mut Commands, entity: Entity, component: impl Component) {
+    commands.add(move |world: &mut World| {
+        if let Some(mut entity) = world.get_entity_mut(entity) {
+            entity.insert(component);
+        }
+    });
+}
+
+/// True when the cursor is over a UI element such as the minimap, so that
+/// clicks aren't passed through to the graph behind it
+fn cursor_over_ui(q_interactions: &Query<&Interaction>) -> bool {
+    q_interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None)
 }
 
 fn update_inspection(
     mut commands: Commands,
     mut knyst_data: NonSendMut<KnystData>,
-    mut graph_query: Query<(&mut Graph)>,
-    mut node_query: Query<(&mut Node, Entity)>,
+    node_query: Query<(&Node, Entity)>,
     mut q_graph_output: Query<(&mut GraphOutputs, Entity)>,
+    mut q_graph_input: Query<(&mut GraphInputs, Entity)>,
+    mut q_sprites: Query<(&Parent, &mut Sprite)>,
+    edge_query: Query<(Entity, &NodeEdge)>,
     asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut inspection_requests: EventReader<RequestInspection>,
+    mut inspection_updates: EventWriter<InspectionUpdated>,
+    mut graph_view: ResMut<GraphView>,
+    level_meters: Option<Res<LevelMeters>>,
+    mut placements: ResMut<palette::Placements>,
 ) {
     let mut new_inspection_available = false;
+    knyst_data
+        .poll_timer
+        .set_duration(settings.inspection_poll_interval);
+    knyst_data.poll_timer.tick(time.delta());
+    let poll_now = settings.inspection_poll_interval.is_zero() || knyst_data.poll_timer.finished();
     if let Some(recv) = &mut knyst_data.next_receiver {
-        if let Ok(new_inspection) = recv.try_recv() {
-            knyst_data.latest_inspection = new_inspection;
-            new_inspection_available = true;
+        if poll_now {
+            match recv.try_recv() {
+                Ok(new_inspection) => {
+                    knyst_data.latest_inspection = new_inspection;
+                    knyst_data.next_receiver = None;
+                    new_inspection_available = true;
+                    inspection_updates.send(InspectionUpdated);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    warn!("Inspection request was dropped, requesting a new one");
+                    knyst_data.next_receiver = None;
+                    knyst_data.last_request = None;
+                }
+                Err(TryRecvError::Empty) => (),
+            }
         }
-    } else {
-        let inspection_receiver = knyst().request_inspection();
-        knyst_data.next_receiver = Some(inspection_receiver);
     }
-    let font = asset_server.load("fonts/Terminess (TTF) Bold Nerd Font Complete.ttf");
+    // Only one request is in flight at a time. A manual request is served as
+    // soon as the previous one has arrived.
+    let requested_manually = inspection_requests.read().count() > 0
+        || settings
+            .reinspect_key
+            .is_some_and(|key| keys.just_pressed(key));
+    if requested_manually {
+        knyst_data.last_request = None;
+    }
+    if knyst_data.next_receiver.is_none() {
+        let interval_passed = match knyst_data.last_request {
+            Some(last) => time.elapsed() - last >= settings.reinspection_interval,
+            None => true,
+        };
+        if interval_passed {
+            let inspection_receiver = knyst().request_inspection();
+            knyst_data.next_receiver = Some(inspection_receiver);
+            knyst_data.last_request = Some(time.elapsed());
+        }
+    }
+    let font = asset_server.load(&settings.font_path);
     let text_style = TextStyle {
         font: font.clone(),
         font_size: 20.0,
@@ -103,84 +560,113 @@ fn update_inspection(
     };
     let text_alignment = TextAlignment::Center;
     let mut rng = thread_rng();
-    let mut edges_to_add = vec![];
     let mut new_nodes = vec![];
-    if new_inspection_available {
-        info!("New inspeciton available");
-        if q_graph_output.is_empty() {
-            let graph_outputs = knyst_data.latest_inspection.num_outputs;
-            // Spawn a new node
-            let parent = commands
-                .spawn((
-                    SpatialBundle {
-                        transform: Transform::from_translation(Vec3::new(500., 0., 0.)),
-                        ..Default::default()
-                    },
-                    Velocity(Vec2::ZERO),
-                    GraphOutputs {
-                        num_outputs: graph_outputs,
-                        graph_id: knyst_data.latest_inspection.graph_id,
-                    },
-                ))
-                .id();
-            let mut children = Vec::new();
-            let rect = commands
-                .spawn((SpriteBundle {
-                    sprite: Sprite {
-                        color: Color::rgb(0.0, 0.25, 0.75),
-                        custom_size: Some(Vec2::new(160.0, 15. * graph_outputs as f32)),
-                        ..default()
-                    },
-                    transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
-                    ..default()
-                },))
-                .id();
-            let name = commands
-                .spawn((Text2dBundle {
-                    text: Text::from_section("GraphOutputs", text_style.clone())
-                        .with_alignment(text_alignment),
-                    ..default()
-                },))
-                .id();
-            children.push(rect);
-            children.push(name);
-            commands.entity(parent).push_children(&children);
-            for edge in &knyst_data.latest_inspection.graph_output_input_edges {
-                edges_to_add.push((*edge, parent));
+    if new_inspection_available || knyst_data.view_dirty || graph_view.is_changed() {
+        debug!("New inspection available");
+        knyst_data.view_dirty = false;
+        let (inspection, depth) = graph_view.resolve(&knyst_data.latest_inspection);
+        let graph_id = inspection.graph_id;
+        if depth < graph_view.path.len() {
+            // The graph we were looking at has been freed
+            graph_view.path.truncate(depth);
+        }
+        // Entities from different graphs can't be mixed. When another graph
+        // is shown, clear everything and rebuild from scratch next frame.
+        let shown_graph = q_graph_output
+            .iter()
+            .map(|(go, _)| go.graph_id)
+            .chain(q_graph_input.iter().map(|(gi, _)| gi.graph_id))
+            .next();
+        if shown_graph.is_some_and(|id| id != graph_id) {
+            for (_, entity) in &node_query {
+                commands.entity(entity).despawn_recursive();
+            }
+            for (_, entity) in &q_graph_output {
+                commands.entity(entity).despawn_recursive();
+            }
+            for (_, entity) in &q_graph_input {
+                commands.entity(entity).despawn_recursive();
             }
+            for (entity, _) in &edge_query {
+                commands.entity(entity).despawn();
+            }
+            knyst_data.view_dirty = true;
+            return;
+        }
+        let knyst_data: &KnystData = &knyst_data;
+        let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+        if q_graph_output.is_empty() {
+            let graph_outputs = inspection.num_outputs;
+            let parent = spawn_graph_io(
+                &mut commands,
+                GraphOutputs {
+                    num_outputs: graph_outputs,
+                    graph_id: inspection.graph_id,
+                },
+                "GraphOutputs",
+                graph_outputs,
+                settings.graph_outputs_position,
+                &text_style,
+                &settings,
+            );
+            new_nodes.push((parent, EdgeEndpoint::GraphOutputs));
+        }
+        let graph_inputs = inspection.num_inputs;
+        if q_graph_input.is_empty() && graph_inputs > 0 {
+            let parent = spawn_graph_io(
+                &mut commands,
+                GraphInputs {
+                    num_inputs: graph_inputs,
+                    graph_id: inspection.graph_id,
+                },
+                "GraphInputs",
+                graph_inputs,
+                settings.graph_outputs_position - Vec2::new(settings.column_width(), 0.),
+                &text_style,
+                &settings,
+            );
+            new_nodes.push((parent, EdgeEndpoint::GraphInputs));
         }
-        for node in &knyst_data.latest_inspection.nodes {
+        for node in &inspection.nodes {
             if !node_query.iter().any(|n| n.0.id == node.address) {
-                let size = node.input_channels.len().max(node.output_channels.len()) + 1;
+                // Nodes added from the palette stay where they were added
+                let placement = placements.take(node.address);
+                let position = placement.unwrap_or_else(|| {
+                    Vec2::new(rng.gen_range(-300.0..300.), rng.gen_range(-300.0..300.0))
+                });
                 // Spawn a new node
                 let parent = commands
                     .spawn((
                         SpatialBundle {
-                            transform: Transform::from_translation(Vec3::new(
-                                rng.gen_range(-300.0..300.),
-                                rng.gen_range(-300.0..300.0),
-                                0.,
-                            )),
+                            transform: Transform::from_translation(position.extend(0.)),
                             ..Default::default()
                         },
                         Velocity(Vec2::ZERO),
+                        LayoutTarget::default(),
                         Node {
                             id: node.address,
                             num_inputs: node.input_channels.len(),
                             num_outputs: node.output_channels.len(),
-                            edge_acceleration: 1.0,
                         },
                     ))
                     .id();
+                if placement.is_some() {
+                    commands.entity(parent).insert(Pinned);
+                }
+                if node.graph_inspection.is_some() {
+                    commands.entity(parent).insert(Graph);
+                }
                 let mut children = Vec::new();
+                let height = node_height(
+                    node.input_channels.len(),
+                    node.output_channels.len(),
+                    &settings,
+                );
                 let rect = commands
                     .spawn((SpriteBundle {
                         sprite: Sprite {
                             color: Color::rgb(0.0, 0.25, 0.75),
-                            custom_size: Some(Vec2::new(
-                                160.0,
-                                node_height(node.input_channels.len(), node.output_channels.len()),
-                            )),
+                            custom_size: Some(Vec2::new(settings.node_width, height)),
                             ..default()
                         },
                         transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
@@ -212,7 +698,11 @@ fn update_inspection(
                         .spawn((Text2dBundle {
                             text: Text::from_section(input, channel_text_style.clone())
                                 .with_alignment(TextAlignment::Left),
-                            transform: Transform::from_xyz(-80., i as f32 * -15., 0.),
+                            transform: Transform::from_xyz(
+                                settings.node_width * -0.5,
+                                port_y_offset(i, height, &settings),
+                                0.,
+                            ),
                             ..default()
                         },))
                         .id();
@@ -223,212 +713,416 @@ fn update_inspection(
                         .spawn((Text2dBundle {
                             text: Text::from_section(output, channel_text_style.clone())
                                 .with_alignment(TextAlignment::Right),
-                            transform: Transform::from_xyz(80., i as f32 * -15., 0.),
+                            transform: Transform::from_xyz(
+                                settings.node_width * 0.5,
+                                port_y_offset(i, height, &settings),
+                                0.,
+                            ),
                             ..default()
                         },))
                         .id();
                     children.push(text);
                 }
-                commands.entity(parent).push_children(&children);
-                for edge in &node.input_edges {
-                    edges_to_add.push((*edge, parent));
+                if level_meters.is_some() {
+                    children.extend(metering::spawn_level_meters(
+                        &mut commands,
+                        node.address,
+                        node.output_channels.len(),
+                        height,
+                        &settings,
+                    ));
                 }
-                new_nodes.push((parent, node.address));
+                commands.entity(parent).push_children(&children);
+                new_nodes.push((parent, EdgeEndpoint::Node(node.address)));
             }
         }
-        for (edge, sink_node_entity) in edges_to_add {
-            // Find the source entity
-            let source = match edge.source {
-                knyst::inspection::EdgeSource::Node(index) => {
-                    let id = knyst_data.latest_inspection.nodes[index].address;
-                    if let Some((_node, entity)) =
-                        node_query.iter().find(|(node, _ent)| node.id == id)
-                    {
-                        warn!("Found entity");
-                        Some(entity)
-                    } else {
-                        if let Some((entity, _id)) =
-                            new_nodes.iter().find(|(_entity, nid)| *nid == id)
-                        {
-                            warn!("Found entity among new");
-                            Some(*entity)
-                        } else {
-                            warn!("Unable to find entity");
-                            None
-                        }
-                    }
-                }
-                knyst::inspection::EdgeSource::Graph => todo!(),
-            };
-
-            if let Some(source) = source {
-                commands.spawn(NodeEdge {
-                    from_entity: source,
-                    to_entity: sink_node_entity,
-                    from_channel_index: edge.from_index,
-                    to_channel_index: edge.to_index,
-                });
-            }
+        reconcile_edges(
+            &mut commands,
+            inspection,
+            &node_query,
+            &q_graph_output,
+            &q_graph_input,
+            &edge_query,
+            &new_nodes,
+        );
+        despawn_stale_nodes(&mut commands, inspection, &node_query);
+        reconcile_graph_io(
+            &mut commands,
+            inspection,
+            &mut q_graph_output,
+            &mut q_graph_input,
+            &mut q_sprites,
+            &settings,
+        );
+    }
+}
+
+/// Spawn the entity representing the inputs or outputs of the inspected graph
+fn spawn_graph_io(
+    commands: &mut Commands,
+    marker: impl Component,
+    name: &str,
+    num_channels: usize,
+    position: Vec2,
+    text_style: &TextStyle,
+    settings: &VisualiserSettings,
+) -> Entity {
+    let parent = commands
+        .spawn((
+            SpatialBundle {
+                transform: Transform::from_translation(position.extend(0.)),
+                ..Default::default()
+            },
+            Velocity(Vec2::ZERO),
+            LayoutTarget::default(),
+            marker,
+        ))
+        .id();
+    let rect = commands
+        .spawn((SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.0, 0.25, 0.75),
+                custom_size: Some(Vec2::new(
+                    settings.node_width,
+                    node_height(num_channels, num_channels, settings),
+                )),
+                ..default()
+            },
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
+            ..default()
+        },))
+        .id();
+    let name = commands
+        .spawn((Text2dBundle {
+            text: Text::from_section(name, text_style.clone())
+                .with_alignment(TextAlignment::Center),
+            ..default()
+        },))
+        .id();
+    commands.entity(parent).push_children(&[rect, name]);
+    parent
+}
+
+/// Despawn every [`Node`] that is no longer part of `inspection`, together
+/// with its children. Edges to removed nodes are taken care of by
+/// [`reconcile_edges`].
+fn despawn_stale_nodes(
+    commands: &mut Commands,
+    inspection: &GraphInspection,
+    node_query: &Query<(&Node, Entity)>,
+) {
+    let current_nodes: HashSet<NodeId> = inspection.nodes.iter().map(|n| n.address).collect();
+    let mut num_removed = 0;
+    for (node, entity) in node_query.iter() {
+        if !current_nodes.contains(&node.id) {
+            commands.entity(entity).despawn_recursive();
+            num_removed += 1;
         }
+    }
+    if num_removed > 0 {
+        info!("Removed {num_removed} nodes");
+    }
+}
 
-        for g in &mut graph_query {}
+/// Update the number of channels of the graph inputs and outputs to match
+/// `inspection`. The graph inputs are despawned when there are none left.
+fn reconcile_graph_io(
+    commands: &mut Commands,
+    inspection: &GraphInspection,
+    q_graph_output: &mut Query<(&mut GraphOutputs, Entity)>,
+    q_graph_input: &mut Query<(&mut GraphInputs, Entity)>,
+    q_sprites: &mut Query<(&Parent, &mut Sprite)>,
+    settings: &VisualiserSettings,
+) {
+    let mut resize = |entity: Entity, num_channels: usize| {
+        for (parent, mut sprite) in q_sprites.iter_mut() {
+            if parent.get() == entity {
+                sprite.custom_size = Some(Vec2::new(
+                    settings.node_width,
+                    node_height(num_channels, num_channels, settings),
+                ));
+            }
+        }
+    };
+    for (mut go, entity) in q_graph_output.iter_mut() {
+        if go.num_outputs != inspection.num_outputs {
+            go.num_outputs = inspection.num_outputs;
+            resize(entity, go.num_outputs);
+        }
+    }
+    for (mut gi, entity) in q_graph_input.iter_mut() {
+        if inspection.num_inputs == 0 {
+            commands.entity(entity).despawn_recursive();
+        } else if gi.num_inputs != inspection.num_inputs {
+            gi.num_inputs = inspection.num_inputs;
+            resize(entity, gi.num_inputs);
+        }
     }
 }
 
-fn draw_edges(
-    mut gizmos: Gizmos,
-    node_query: Query<(&Node, &Transform)>,
-    graph_output_query: Query<(&GraphOutputs, &Transform)>,
-    edge_query: Query<(&NodeEdge)>,
+/// Make the set of [`NodeEdge`] entities match the edges in `inspection`
+/// exactly: edges that are gone or dangling are despawned, new ones spawned.
+///
+/// `new_nodes` are the entities spawned this frame, which aren't visible to
+/// the queries yet.
+fn reconcile_edges(
+    commands: &mut Commands,
+    inspection: &GraphInspection,
+    node_query: &Query<(&Node, Entity)>,
+    q_graph_output: &Query<(&mut GraphOutputs, Entity)>,
+    q_graph_input: &Query<(&mut GraphInputs, Entity)>,
+    edge_query: &Query<(Entity, &NodeEdge)>,
+    new_nodes: &[(Entity, EdgeEndpoint)],
 ) {
-    for edge in edge_query.iter() {
-        let NodeEdge {
-            from_entity,
-            to_entity,
-            from_channel_index,
-            to_channel_index,
-        } = edge;
-        let origin_pos = if let Ok((_, from_node_transform)) = node_query.get(*from_entity) {
-            from_node_transform.translation.xy()
-                + Vec2::new(80., *from_channel_index as f32 * -15.0 + 7.5)
-        } else {
-            Vec2::new(0.0, 0.0)
-        };
+    let mut entities: HashMap<EdgeEndpoint, Entity> = node_query
+        .iter()
+        .map(|(node, entity)| (EdgeEndpoint::Node(node.id), entity))
+        .chain(
+            q_graph_output
+                .iter()
+                .map(|(_, entity)| (EdgeEndpoint::GraphOutputs, entity)),
+        )
+        .chain(
+            q_graph_input
+                .iter()
+                .map(|(_, entity)| (EdgeEndpoint::GraphInputs, entity)),
+        )
+        .collect();
+    entities.extend(
+        new_nodes
+            .iter()
+            .map(|(entity, endpoint)| (*endpoint, *entity)),
+    );
+    let endpoints: HashMap<Entity, EdgeEndpoint> = entities
+        .iter()
+        .map(|(endpoint, entity)| (*entity, *endpoint))
+        .collect();
 
-        let end_pos = if let Ok((_, to_node_transform)) = node_query.get(*to_entity) {
-            to_node_transform.translation.xy()
-                + Vec2::new(-80., *to_channel_index as f32 * -15.0 + 7.5)
-        } else {
-            if let Ok((_, to_graph_transform)) = graph_output_query.get(*to_entity) {
-                to_graph_transform.translation.xy()
-                    + Vec2::new(-80., *to_channel_index as f32 * -15.0 + 7.5)
-            } else {
-                Vec2::new(0., 0.)
+    let sinks = inspection
+        .graph_output_input_edges
+        .iter()
+        .map(|edge| (edge, EdgeEndpoint::GraphOutputs))
+        .chain(inspection.nodes.iter().flat_map(|node| {
+            node.input_edges
+                .iter()
+                .map(|edge| (edge, EdgeEndpoint::Node(node.address)))
+        }));
+    let mut wanted_edges = HashSet::new();
+    for (edge, sink) in sinks {
+        let source = match edge.source {
+            knyst::inspection::EdgeSource::Node(index) => {
+                EdgeEndpoint::Node(inspection.nodes[index].address)
             }
+            knyst::inspection::EdgeSource::Graph => EdgeEndpoint::GraphInputs,
+        };
+        wanted_edges.insert(EdgeKey {
+            source,
+            from_index: edge.from_index,
+            sink,
+            to_index: edge.to_index,
+        });
+    }
+
+    for (edge_entity, edge) in edge_query.iter() {
+        let key = endpoints
+            .get(&edge.from_entity)
+            .zip(endpoints.get(&edge.to_entity))
+            .map(|(source, sink)| EdgeKey {
+                source: *source,
+                from_index: edge.from_channel_index,
+                sink: *sink,
+                to_index: edge.to_channel_index,
+            });
+        // Whatever is left in `wanted_edges` afterwards needs to be spawned
+        match key {
+            Some(key) if wanted_edges.remove(&key) => (),
+            _ => commands.entity(edge_entity).despawn(),
+        }
+    }
+
+    for key in wanted_edges {
+        let (Some(from_entity), Some(to_entity)) =
+            (entities.get(&key.source), entities.get(&key.sink))
+        else {
+            warn!("Unable to find entities for edge {key:?}");
+            continue;
         };
-        gizmos.line_2d(origin_pos, end_pos, Color::RED);
+        commands.spawn(NodeEdge {
+            from_entity: *from_entity,
+            to_entity: *to_entity,
+            from_channel_index: key.from_index,
+            to_channel_index: key.to_index,
+        });
     }
 }
 
-fn update_velocities(
-    mut node_query: Query<(&mut Node, &Transform, &mut Velocity)>,
-    mut q_graph_outputs: Query<(&mut GraphOutputs, &Transform)>,
-    edge_query: Query<&NodeEdge>,
+fn draw_edges(
+    mut gizmos: Gizmos,
+    q_edge_ends: Query<EdgeEnd>,
+    edge_query: Query<(&NodeEdge, Has<FeedbackEdge>)>,
+    settings: Res<VisualiserSettings>,
 ) {
-    for (_node, _transform, mut vel) in node_query.iter_mut() {
-        vel.0 *= Vec2::splat(0.5);
-    }
-    for edge in edge_query.iter() {
-        let NodeEdge {
-            from_entity,
-            to_entity,
-            from_channel_index,
-            to_channel_index,
-        } = edge;
-        let origin_pos = if let Ok((_, from_node_transform, _vel)) = node_query.get(*from_entity) {
-            from_node_transform.translation.xy()
+    for (edge, is_feedback) in edge_query.iter() {
+        let Some(path) = edges::path_of(edge, &q_edge_ends, &settings) else {
+            continue;
+        };
+        // Feedback goes against the flow of the graph, make it stand out with
+        // a dashed line
+        let color = if is_feedback {
+            Color::ORANGE
         } else {
-            Vec2::new(0.0, 0.0)
+            Color::RED
         };
+        edges::draw_edge_path(&mut gizmos, &path, color, is_feedback);
+    }
+}
 
-        let mut to_node_transform_pos = None;
-        if let Ok((node, to_node_transform, mut vel)) = node_query.get_mut(*to_entity) {
-            let end_pos = to_node_transform.translation.xy();
-            to_node_transform_pos = Some(to_node_transform.translation.xy());
-            let diff = origin_pos - end_pos
-                + Vec2::new(
-                    180.,
-                    -15. * *to_channel_index as f32 + (node.num_inputs as f32 * 15. * 0.5),
-                );
-            if diff.length_squared() > 60. {
-                vel.0 += diff.clamp_length_max(50.) * 0.15 * node.edge_acceleration;
-            } else {
-                vel.0 -= diff * 0.15 * node.edge_acceleration;
-            }
+/// Find the edges that close a feedback loop.
+///
+/// Searches depth first against the signal flow, starting from each of
+/// `roots` in turn. Every edge leading back to a node that is still on the
+/// search stack is a feedback edge. Without them the graph is acyclic.
+fn feedback_edges<'a>(
+    edges: impl Iterator<Item = (Entity, &'a NodeEdge)>,
+    roots: impl Iterator<Item = Entity>,
+) -> HashSet<Entity> {
+    let mut incoming: HashMap<Entity, Vec<(Entity, Entity)>> = HashMap::new();
+    for (edge_entity, edge) in edges {
+        incoming
+            .entry(edge.to_entity)
+            .or_default()
+            .push((edge_entity, edge.from_entity));
+    }
+    let mut on_stack = HashSet::new();
+    let mut done = HashSet::new();
+    let mut feedback = HashSet::new();
+    for root in roots {
+        if done.contains(&root) {
+            continue;
         }
-        if let Ok((mut node, from_node_transform, mut vel)) = node_query.get_mut(*from_entity) {
-            if let Some(origin_pos) = to_node_transform_pos {
-                let end_pos = from_node_transform.translation.xy();
-                let diff =
-                    origin_pos - end_pos + Vec2::new(-180., -15. * *from_channel_index as f32);
-                if diff.length_squared() > 60. {
-                    vel.0 += diff.clamp_length_max(50.) * 0.15;
-                } else {
-                    vel.0 -= diff * 0.15;
+        let mut stack = vec![(root, 0)];
+        on_stack.insert(root);
+        while let Some((node, next)) = stack.last_mut() {
+            let node = *node;
+            match incoming.get(&node).and_then(|inputs| inputs.get(*next)) {
+                Some(&(edge_entity, source)) => {
+                    *next += 1;
+                    if on_stack.contains(&source) {
+                        feedback.insert(edge_entity);
+                    } else if !done.contains(&source) {
+                        on_stack.insert(source);
+                        stack.push((source, 0));
+                    }
+                }
+                None => {
+                    on_stack.remove(&node);
+                    done.insert(node);
+                    stack.pop();
                 }
-            }
-            if node.edge_acceleration > 0.1 {
-                node.edge_acceleration *= 0.95;
             }
         }
     }
-    // Move away from other nodes
-    // This force should be weaker than the force from edges when the edges are far apart.
-    let mut combinations = node_query.iter_combinations_mut();
-    while let Some([mut n0, mut n1]) = combinations.fetch_next() {
-        let diff = n0.1.translation.xy() - n1.1.translation.xy();
-        if diff.length_squared() < 100. {
-            let vel = diff.normalize();
-            n0.2 .0 += vel * 4.0;
-            n1.2 .0 += vel * 4.0;
+    feedback
+}
+
+/// Marks edges that are part of a feedback loop so that they can be drawn
+/// differently
+#[derive(Component)]
+struct FeedbackEdge;
+
+fn mark_feedback_edges(
+    mut commands: Commands,
+    edge_query: Query<(Entity, &NodeEdge, Has<FeedbackEdge>)>,
+    q_graph_outputs: Query<Entity, With<GraphOutputs>>,
+    q_node_entities: Query<Entity, With<Node>>,
+) {
+    let roots = q_graph_outputs.iter().chain(q_node_entities.iter());
+    let feedback = feedback_edges(edge_query.iter().map(|(e, edge, _)| (e, edge)), roots);
+    for (entity, _, marked) in &edge_query {
+        match (feedback.contains(&entity), marked) {
+            (true, false) => try_insert(&mut commands, entity, FeedbackEdge),
+            (false, true) => {
+                commands.entity(entity).remove::<FeedbackEdge>();
+            }
+            _ => (),
         }
     }
 }
 
-fn apply_velocities(mut node_query: Query<(&Node, &mut Transform, &Velocity)>) {
-    for (_node, mut transform, vel) in node_query.iter_mut() {
-        transform.translation += Vec3::from((vel.0, 0.));
-    }
+/// The area covered by [`place_columns`]
+struct ColumnsExtent {
+    /// Every entity reached, including the first column
+    visited: HashSet<Entity>,
+    /// Lowest y coordinate of any placed node
+    bottom: f32,
+    /// x coordinate of the leftmost column containing a node
+    leftmost: f32,
 }
 
-fn move_nodes(
-    mut node_query: Query<(&mut Node, &mut Transform), Without<GraphOutputs>>,
-    q_graph_outputs: Query<(&Transform, Entity, &GraphOutputs)>,
-    edge_query: Query<&NodeEdge>,
-) {
-    let mut node_entities_in_current_column = vec![];
+/// Put the inputs to `first_column` in a column at `x`, then their inputs in
+/// the column to the left of that and so on until there are no more inputs.
+/// A node that is reached more than once ends up in the column furthest to
+/// the left. Nodes in `fixed` are neither moved nor followed.
+///
+/// The chosen positions are written to `plan`. Only entities in `heights`
+/// are placed.
+fn place_columns(
+    first_column: Vec<Entity>,
+    fixed: &HashSet<Entity>,
+    first_column_height: f32,
+    x: f32,
+    start_y: f32,
+    heights: &HashMap<Entity, f32>,
+    edges: &[&NodeEdge],
+    settings: &VisualiserSettings,
+    plan: &mut HashMap<Entity, Vec2>,
+) -> ColumnsExtent {
+    let column_size = settings.column_width();
+    let row_gap = settings.row_gap;
+    let mut visited: HashSet<Entity> = first_column.iter().copied().collect();
+    let mut node_entities_in_current_column = first_column;
     let mut node_entities_to_put_in_the_next_column = vec![];
-    // First find the inputs to the GraphOutputs and to nodes that are unconnected to the graph outputs.
-    // TODO: unconnected nodes
-    let column_size = 180.;
-    let row_gap = 10.;
-    let Ok((go_transform, go_entity, go)) = q_graph_outputs.get_single() else {
-        return;
-    };
-    node_entities_in_current_column.push(go_entity);
-    let start_y = go_transform.translation.y;
-    let mut current_column = go_transform.translation.x - column_size;
-    let mut previous_column_height = node_height(go.num_outputs, go.num_outputs);
+    let mut current_column = x;
+    let mut previous_column_height = first_column_height;
+    let mut leftmost_column = x + column_size;
+    let mut bottom = start_y;
     while !node_entities_in_current_column.is_empty() {
-        for edge in edge_query.iter() {
-            if node_entities_in_current_column.contains(&edge.to_entity) {
-                if !node_entities_to_put_in_the_next_column.contains(&edge.from_entity) {
-                    node_entities_to_put_in_the_next_column.push(edge.from_entity);
-                }
+        for edge in edges {
+            if node_entities_in_current_column.contains(&edge.to_entity)
+                && !fixed.contains(&edge.from_entity)
+                && !node_entities_to_put_in_the_next_column.contains(&edge.from_entity)
+            {
+                node_entities_to_put_in_the_next_column.push(edge.from_entity);
             }
         }
-        // 
         let mut y = 0.;
         for node_entity in &node_entities_to_put_in_the_next_column {
             // Move
-            if let Ok((node, mut transform)) = node_query.get_mut(*node_entity) {
-                transform.translation.x = current_column;
-                transform.translation.y = y + start_y;
-                y -= node_height(node.num_inputs, node.num_outputs) + row_gap;
+            if let Some(height) = heights.get(node_entity) {
+                plan.insert(*node_entity, Vec2::new(current_column, y + start_y));
+                y -= height + row_gap;
+                leftmost_column = current_column;
             }
         }
         if previous_column_height.abs() > (y).abs() {
             y = 0.;
-            let row_gap = (previous_column_height.abs() - (y).abs()) / (node_entities_to_put_in_the_next_column.len()+1) as f32;
-        for node_entity in &node_entities_to_put_in_the_next_column {
-            // Move
-            if let Ok((node, mut transform)) = node_query.get_mut(*node_entity) {
-                transform.translation.x = current_column;
-                transform.translation.y = y + start_y;
-                y -= node_height(node.num_inputs, node.num_outputs) + row_gap;
+            let row_gap = (previous_column_height.abs() - (y).abs())
+                / (node_entities_to_put_in_the_next_column.len() + 1) as f32;
+            for node_entity in &node_entities_to_put_in_the_next_column {
+                // Move
+                if let Some(height) = heights.get(node_entity) {
+                    plan.insert(*node_entity, Vec2::new(current_column, y + start_y));
+                    y -= height + row_gap;
+                }
             }
         }
-
+        for node_entity in &node_entities_to_put_in_the_next_column {
+            visited.insert(*node_entity);
+            if let (Some(height), Some(position)) =
+                (heights.get(node_entity), plan.get(node_entity))
+            {
+                bottom = bottom.min(position.y - height * 0.5);
+            }
         }
         current_column -= column_size;
         std::mem::swap(
@@ -438,36 +1132,474 @@ fn move_nodes(
         node_entities_to_put_in_the_next_column.clear();
         previous_column_height = y;
     }
+    ColumnsExtent {
+        visited,
+        bottom,
+        leftmost: leftmost_column,
+    }
+}
 
-    // Recursively add any inputs to the current column until there are no more
+/// Group the nodes that haven't been placed yet into connected components.
+/// The graph inputs and outputs have columns of their own, so nodes are not
+/// connected through them.
+fn unplaced_components(
+    placed: &HashSet<Entity>,
+    node_entities: impl Iterator<Item = Entity>,
+    edges: &[&NodeEdge],
+) -> Vec<Vec<Entity>> {
+    let node_entities: Vec<Entity> = node_entities.collect();
+    let is_node: HashSet<Entity> = node_entities.iter().copied().collect();
+    let mut neighbours: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for edge in edges
+        .iter()
+        .filter(|edge| is_node.contains(&edge.from_entity) && is_node.contains(&edge.to_entity))
+    {
+        neighbours
+            .entry(edge.from_entity)
+            .or_default()
+            .push(edge.to_entity);
+        neighbours
+            .entry(edge.to_entity)
+            .or_default()
+            .push(edge.from_entity);
+    }
+    let mut seen = placed.clone();
+    let mut components = vec![];
+    for start in node_entities {
+        if !seen.insert(start) {
+            continue;
+        }
+        let mut component = vec![start];
+        let mut i = 0;
+        while i < component.len() {
+            for neighbour in neighbours.get(&component[i]).into_iter().flatten() {
+                if seen.insert(*neighbour) {
+                    component.push(*neighbour);
+                }
+            }
+            i += 1;
+        }
+        components.push(component);
+    }
+    components
 }
 
-fn move_camera_mouse(
-    q_windows: Query<&Window, With<PrimaryWindow>>,
-    mut q_camera: Query<&mut Transform, With<GameCamera>>,
+fn move_nodes(
+    mut node_query: Query<
+        (Entity, &Node, &Transform, &mut LayoutTarget, Has<Pinned>),
+        Without<GraphOutputs>,
+    >,
+    q_graph_outputs: Query<(&Transform, Entity, &GraphOutputs)>,
+    mut q_graph_inputs: Query<
+        (&Transform, &mut LayoutTarget),
+        (With<GraphInputs>, Without<Node>, Without<GraphOutputs>),
+    >,
+    edge_query: Query<(Entity, &NodeEdge)>,
+    settings: Res<VisualiserSettings>,
 ) {
-    // Games typically only have one window (the primary window)
-    if let Some(position) = q_windows.single().cursor_position() {
-        println!("Cursor is inside the primary window, at {:?}", position);
-        let window_height = q_windows.single().height();
-        let window_width = q_windows.single().width();
-        let margin = 50.;
-        let speed = 5.;
-        let mut vel = Vec2::zeroed();
-        if position.x < margin {
-            vel += Vec2::new(speed * -1., 0.0);
-        }
-        if position.y < margin {
-            vel += Vec2::new(0.0, speed);
-        }
-        if position.x > window_width - margin {
-            vel += Vec2::new(speed, 0.0);
-        }
-        if position.y > window_height - margin {
-            vel += Vec2::new(0.0, speed * -1.);
-        }
-        q_camera.single_mut().translation += Vec3::from((vel, 0.0));
-    } else {
-        println!("Cursor is not in the game window.");
+    // First find the inputs to the GraphOutputs and then to nodes that are unconnected to the graph outputs.
+    let column_size = settings.column_width();
+    let Ok((go_transform, go_entity, go)) = q_graph_outputs.get_single() else {
+        return;
+    };
+    let start_y = go_transform.translation.y;
+    let go_height = node_height(go.num_outputs, go.num_outputs, &settings);
+    let heights: HashMap<Entity, f32> = node_query
+        .iter()
+        .map(|(entity, node, ..)| {
+            (
+                entity,
+                node_height(node.num_inputs, node.num_outputs, &settings),
+            )
+        })
+        .collect();
+    let node_entities: Vec<Entity> = node_query.iter().map(|(entity, ..)| entity).collect();
+    // Following feedback edges would make the columns go on forever
+    let roots = std::iter::once(go_entity).chain(node_entities.iter().copied());
+    let feedback = feedback_edges(edge_query.iter(), roots);
+    let edges: Vec<&NodeEdge> = edge_query
+        .iter()
+        .filter(|(entity, _)| !feedback.contains(entity))
+        .map(|(_, edge)| edge)
+        .collect();
+    let mut plan = HashMap::new();
+    let main = place_columns(
+        vec![go_entity],
+        &HashSet::new(),
+        go_height,
+        go_transform.translation.x - column_size,
+        start_y,
+        &heights,
+        &edges,
+        &settings,
+        &mut plan,
+    );
+
+    // The graph inputs always go in a column of their own to the left of everything else
+    if let Ok((transform, mut target)) = q_graph_inputs.get_single_mut() {
+        target.set(
+            Vec2::new(main.leftmost - column_size, start_y),
+            transform.translation.xy(),
+        );
+    }
+
+    // Every group of nodes that doesn't lead to the graph outputs gets its
+    // own columns below the main graph, starting from the nodes whose outputs
+    // aren't used.
+    let mut region_top = main.bottom.min(start_y - go_height * 0.5) - settings.component_gap;
+    let components = unplaced_components(&main.visited, node_entities.iter().copied(), &edges);
+    for component in components {
+        let mut sinks: Vec<Entity> = component
+            .iter()
+            .copied()
+            .filter(|entity| {
+                !edges
+                    .iter()
+                    .any(|edge| edge.from_entity == *entity && component.contains(&edge.to_entity))
+            })
+            .collect();
+        if sinks.is_empty() {
+            // Only feedback loops, start anywhere
+            sinks.push(component[0]);
+        }
+        let tallest = component
+            .iter()
+            .filter_map(|entity| heights.get(entity))
+            .fold(0., |a: f32, b| a.max(*b));
+        let component_y = region_top - tallest * 0.5;
+        let mut y = 0.;
+        let mut bottom = region_top;
+        for sink in &sinks {
+            if let Some(height) = heights.get(sink) {
+                let position = Vec2::new(go_transform.translation.x - column_size, component_y + y);
+                plan.insert(*sink, position);
+                bottom = bottom.min(position.y - height * 0.5);
+                y -= height + settings.row_gap;
+            }
+        }
+        let extent = place_columns(
+            sinks,
+            &main.visited,
+            -y,
+            go_transform.translation.x - column_size * 2.,
+            component_y,
+            &heights,
+            &edges,
+            &settings,
+            &mut plan,
+        );
+        region_top = extent.bottom.min(bottom) - settings.component_gap;
+    }
+
+    // Pinned nodes stay where the user put them and everything else moves out of their way
+    let pinned: Vec<Rect> = node_query
+        .iter()
+        .filter(|(.., pinned)| *pinned)
+        .map(|(entity, _, transform, ..)| {
+            plan.remove(&entity);
+            Rect::from_center_size(
+                transform.translation.xy(),
+                Vec2::new(settings.node_width, heights[&entity]),
+            )
+        })
+        .collect();
+    avoid_pinned(&mut plan, &heights, &pinned, &settings);
+    for (entity, _, transform, mut target, _) in &mut node_query {
+        if let Some(position) = plan.get(&entity) {
+            target.set(*position, transform.translation.xy());
+        }
+    }
+}
+
+/// Overlaps with pinned nodes smaller than this are ignored, in pixels
+const OVERLAP_TOLERANCE: f32 = 0.01;
+
+/// Move planned positions down where they would overlap a pinned node.
+/// Nodes that are in the same column keep their order and don't overlap each
+/// other.
+fn avoid_pinned(
+    plan: &mut HashMap<Entity, Vec2>,
+    heights: &HashMap<Entity, f32>,
+    pinned: &[Rect],
+    settings: &VisualiserSettings,
+) {
+    if pinned.is_empty() {
+        return;
+    }
+    let gap = settings.row_gap;
+    let mut columns: HashMap<i32, Vec<(Entity, Vec2, f32)>> = HashMap::new();
+    for (entity, position) in plan.iter() {
+        let height = heights.get(entity).copied().unwrap_or(settings.row_height);
+        columns
+            .entry(position.x.round() as i32)
+            .or_default()
+            .push((*entity, *position, height));
+    }
+    for column in columns.values_mut() {
+        column.sort_by(|a, b| b.1.y.total_cmp(&a.1.y));
+        let mut floor = f32::INFINITY;
+        for (entity, position, height) in column.iter() {
+            let mut y = position.y.min(floor - height * 0.5);
+            // Rounding can leave a node moved below a pinned one overlapping
+            // it by a tiny bit. Ignoring that is what makes every move go down
+            // by at least the tolerance, so that this loop ends.
+            while let Some(blocking) = pinned.iter().find(|rect| {
+                let node = Rect::from_center_size(
+                    Vec2::new(position.x, y),
+                    Vec2::new(settings.node_width, height + gap * 2.),
+                );
+                let overlap = node.intersect(**rect);
+                overlap.width() > OVERLAP_TOLERANCE && overlap.height() > OVERLAP_TOLERANCE
+            }) {
+                y = blocking.min.y - gap - height * 0.5;
+            }
+            floor = y - height * 0.5 - gap;
+            plan.insert(*entity, Vec2::new(position.x, y));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use knyst::inspection::{EdgeInspection, EdgeSource, NodeInspection};
+
+    use super::*;
+
+    fn edge(from: u32, to: u32) -> NodeEdge {
+        NodeEdge {
+            from_entity: Entity::from_raw(from),
+            to_entity: Entity::from_raw(to),
+            from_channel_index: 0,
+            to_channel_index: 0,
+        }
+    }
+
+    /// Find the feedback edges of `edges` and lay out the rest in columns
+    /// from entity 0, which stands in for the graph outputs
+    fn feedback_and_columns(edges: &[NodeEdge], num_nodes: u32) -> (usize, ColumnsExtent) {
+        let settings = VisualiserSettings::default();
+        let with_entities: Vec<(Entity, &NodeEdge)> = edges
+            .iter()
+            .enumerate()
+            .map(|(i, edge)| (Entity::from_raw(100 + i as u32), edge))
+            .collect();
+        let roots = (0..=num_nodes).map(Entity::from_raw);
+        let feedback = feedback_edges(with_entities.iter().copied(), roots);
+        let remaining: Vec<&NodeEdge> = with_entities
+            .iter()
+            .filter(|(entity, _)| !feedback.contains(entity))
+            .map(|(_, edge)| *edge)
+            .collect();
+        let heights = (1..=num_nodes)
+            .map(|i| (Entity::from_raw(i), 30.))
+            .collect();
+        let extent = place_columns(
+            vec![Entity::from_raw(0)],
+            &HashSet::new(),
+            30.,
+            0.,
+            0.,
+            &heights,
+            &remaining,
+            &settings,
+            &mut HashMap::new(),
+        );
+        (feedback.len(), extent)
+    }
+
+    #[test]
+    fn two_node_cycle_has_one_feedback_edge() {
+        let (feedback, extent) = feedback_and_columns(&[edge(1, 2), edge(2, 1), edge(2, 0)], 2);
+        assert_eq!(feedback, 1);
+        assert_eq!(extent.visited.len(), 3);
+    }
+
+    #[test]
+    fn self_loop_is_a_feedback_edge() {
+        let (feedback, extent) = feedback_and_columns(&[edge(1, 1), edge(1, 0)], 1);
+        assert_eq!(feedback, 1);
+        assert_eq!(extent.visited.len(), 2);
+    }
+
+    #[test]
+    fn cycle_away_from_the_outputs_has_one_feedback_edge() {
+        let (feedback, extent) = feedback_and_columns(&[edge(1, 2), edge(2, 1)], 2);
+        assert_eq!(feedback, 1);
+        assert_eq!(extent.visited.len(), 1);
+    }
+
+    #[test]
+    fn avoid_pinned_ends_on_rounding_boundaries() {
+        let settings = VisualiserSettings::default();
+        let entity = Entity::from_raw(1);
+        let heights = HashMap::from([(entity, 30.)]);
+        for i in 0..20_000 {
+            // Pinned nodes whose bottom edge is at awkward f32 values, among
+            // them -1017.544 which used to loop forever
+            let bottom = -1017.544 + i as f32 * 0.001_37;
+            let pinned = Rect::new(-50., bottom, 50., bottom + 40.);
+            let mut plan = HashMap::from([(entity, Vec2::new(0., bottom + 20.))]);
+            avoid_pinned(&mut plan, &heights, &[pinned], &settings);
+            let y = plan[&entity].y;
+            assert!(y + 15. <= bottom + OVERLAP_TOLERANCE, "{y} {bottom}");
+        }
+        // A node exactly on the boundary below a pinned node stays where it is
+        let pinned = Rect::new(-50., -1017.544, 50., -977.544);
+        let y = -1017.544 - settings.row_gap - 15.;
+        let mut plan = HashMap::from([(entity, Vec2::new(0., y))]);
+        avoid_pinned(&mut plan, &heights, &[pinned], &settings);
+        assert_eq!(plan[&entity].y, y);
+    }
+
+    fn node_inspection(address: NodeId, input_edges: Vec<EdgeInspection>) -> NodeInspection {
+        NodeInspection {
+            name: "Node".into(),
+            address,
+            input_channels: vec!["in".into()],
+            output_channels: vec!["out".into()],
+            input_edges,
+            graph_inspection: None,
+        }
+    }
+
+    /// `(from_entity, to_entity, edge entity)` of every edge after reconciling
+    /// the edges with `inspection`
+    fn reconciled_edges(
+        world: &mut World,
+        inspection: GraphInspection,
+    ) -> Vec<(Entity, Entity, Entity)> {
+        world.run_system_once(
+            move |mut commands: Commands,
+                  node_query: Query<(&Node, Entity)>,
+                  q_graph_output: Query<(&mut GraphOutputs, Entity)>,
+                  q_graph_input: Query<(&mut GraphInputs, Entity)>,
+                  edge_query: Query<(Entity, &NodeEdge)>| {
+                reconcile_edges(
+                    &mut commands,
+                    &inspection,
+                    &node_query,
+                    &q_graph_output,
+                    &q_graph_input,
+                    &edge_query,
+                    &[],
+                );
+            },
+        );
+        let mut edges: Vec<_> = world
+            .query::<(Entity, &NodeEdge)>()
+            .iter(world)
+            .map(|(entity, edge)| (edge.from_entity, edge.to_entity, entity))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn edges_are_kept_added_and_removed() {
+        let mut world = World::new();
+        let (a, b) = (NodeId::new(), NodeId::new());
+        let mut spawn_node = |id| {
+            world
+                .spawn(Node {
+                    id,
+                    num_inputs: 1,
+                    num_outputs: 1,
+                })
+                .id()
+        };
+        let (a_entity, b_entity) = (spawn_node(a), spawn_node(b));
+        let outputs = world
+            .spawn(GraphOutputs {
+                num_outputs: 1,
+                graph_id: 0,
+            })
+            .id();
+        let from = |index| EdgeInspection {
+            source: EdgeSource::Node(index),
+            from_index: 0,
+            to_index: 0,
+        };
+
+        // a -> b -> outputs
+        let first = reconciled_edges(
+            &mut world,
+            GraphInspection {
+                nodes: vec![
+                    node_inspection(a, vec![]),
+                    node_inspection(b, vec![from(0)]),
+                ],
+                unconnected_nodes: vec![],
+                graph_output_input_edges: vec![from(1)],
+                num_inputs: 0,
+                num_outputs: 1,
+                graph_id: 0,
+            },
+        );
+        assert_eq!(
+            first.iter().map(|e| (e.0, e.1)).collect::<Vec<_>>(),
+            vec![(a_entity, b_entity), (b_entity, outputs)]
+        );
+
+        // a -> b and a -> outputs: the first edge stays, the second is replaced
+        let second = reconciled_edges(
+            &mut world,
+            GraphInspection {
+                nodes: vec![
+                    node_inspection(a, vec![]),
+                    node_inspection(b, vec![from(0)]),
+                ],
+                unconnected_nodes: vec![],
+                graph_output_input_edges: vec![from(0)],
+                num_inputs: 0,
+                num_outputs: 1,
+                graph_id: 0,
+            },
+        );
+        assert_eq!(
+            second.iter().map(|e| (e.0, e.1)).collect::<Vec<_>>(),
+            vec![(a_entity, b_entity), (a_entity, outputs)]
+        );
+        let kept = |edges: &[(Entity, Entity, Entity)]| {
+            edges
+                .iter()
+                .find(|e| (e.0, e.1) == (a_entity, b_entity))
+                .map(|e| e.2)
+        };
+        assert_eq!(kept(&first), kept(&second));
+    }
+
+    #[test]
+    fn graph_io_does_not_join_components() {
+        // 0 is the graph inputs feeding two separate nodes, 1 and 2, which
+        // feed the graph outputs, 3
+        let edges = [edge(0, 1), edge(0, 2), edge(1, 3), edge(2, 3), edge(2, 4)];
+        let edges: Vec<&NodeEdge> = edges.iter().collect();
+        let nodes = [1, 2, 4].map(Entity::from_raw);
+        let components = unplaced_components(&HashSet::new(), nodes.into_iter(), &edges);
+        assert_eq!(
+            components,
+            vec![
+                vec![Entity::from_raw(1)],
+                vec![Entity::from_raw(2), Entity::from_raw(4)]
+            ]
+        );
+    }
+
+    #[test]
+    fn ports_lie_inside_the_node() {
+        let settings = VisualiserSettings::default();
+        for num_inputs in 0..10 {
+            for num_outputs in 0..10 {
+                let height = node_height(num_inputs, num_outputs, &settings);
+                for port in 0..num_inputs.max(num_outputs) {
+                    let offset = port_y_offset(port, height, &settings);
+                    assert!(
+                        offset.abs() + settings.row_height * 0.5 <= height * 0.5 + 1e-3,
+                        "port {port} of {num_inputs}/{num_outputs} at {offset}"
+                    );
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,153 @@
+//! Live output levels of the nodes.
+//!
+//! The inspection only describes the topology of the graph. Levels come from a
+//! separate [`LevelTap`], which can be backed by a probe inside knyst or by a
+//! [`MockLevelTap`] when there is nothing to measure.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{prelude::*, sprite::Anchor};
+use knyst::graph::NodeId;
+
+use crate::{port_y_offset, VisualiserSettings};
+
+/// The level of one output channel over the last block of audio
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Level {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// A source of output levels for the nodes in the graph
+pub trait LevelTap: Send + Sync + 'static {
+    /// The latest level of output `channel` of `node`, or `None` if it isn't
+    /// being measured
+    fn level(&self, node: NodeId, channel: usize) -> Option<Level>;
+}
+
+/// A [`LevelTap`] returning whatever levels have been set on it. Clones share
+/// the same levels, so keep one to update from another thread.
+#[derive(Clone, Default)]
+pub struct MockLevelTap {
+    levels: Arc<Mutex<HashMap<(NodeId, usize), Level>>>,
+}
+
+impl MockLevelTap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_level(&self, node: NodeId, channel: usize, level: Level) {
+        self.levels.lock().unwrap().insert((node, channel), level);
+    }
+    pub fn clear(&self) {
+        self.levels.lock().unwrap().clear();
+    }
+}
+
+impl LevelTap for MockLevelTap {
+    fn level(&self, node: NodeId, channel: usize) -> Option<Level> {
+        self.levels.lock().unwrap().get(&(node, channel)).copied()
+    }
+}
+
+/// The [`LevelTap`] used by the visualiser. Meters are only shown when this
+/// resource exists.
+#[derive(Resource, Clone)]
+pub struct LevelMeters(pub Arc<dyn LevelTap>);
+
+/// Longest meter bar in pixels, reached at 0 dBFS
+const METER_LENGTH: f32 = 40.;
+/// The quietest level shown on a meter
+const METER_FLOOR_DB: f32 = -60.;
+
+/// A bar showing the level of one output channel of a node
+#[derive(Component)]
+pub(crate) struct LevelMeter {
+    node: NodeId,
+    channel: usize,
+    /// Shows the peak level as a thin marker instead of the RMS level as a bar
+    peak: bool,
+}
+
+/// How much of a meter to fill for an amplitude, on a dB scale
+fn meter_fraction(amplitude: f32) -> f32 {
+    if amplitude <= 0. {
+        return 0.;
+    }
+    let db = 20. * amplitude.log10();
+    ((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0., 1.)
+}
+
+/// Spawn meters for every output of a node. The meter scale ends at the right
+/// edge of the node, behind the channel labels.
+pub(crate) fn spawn_level_meters(
+    commands: &mut Commands,
+    node: NodeId,
+    num_outputs: usize,
+    height: f32,
+    settings: &VisualiserSettings,
+) -> Vec<Entity> {
+    let mut meters = Vec::new();
+    let left = settings.node_width * 0.5 - METER_LENGTH;
+    for channel in 0..num_outputs {
+        let y = port_y_offset(channel, height, settings);
+        for peak in [false, true] {
+            let meter = commands
+                .spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::NONE,
+                            custom_size: Some(Vec2::ZERO),
+                            anchor: if peak {
+                                Anchor::Center
+                            } else {
+                                Anchor::CenterLeft
+                            },
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(left, y, 1.),
+                        ..default()
+                    },
+                    LevelMeter {
+                        node,
+                        channel,
+                        peak,
+                    },
+                ))
+                .id();
+            meters.push(meter);
+        }
+    }
+    meters
+}
+
+pub(crate) fn update_level_meters(
+    level_meters: Res<LevelMeters>,
+    settings: Res<VisualiserSettings>,
+    mut q_meters: Query<(&LevelMeter, &mut Sprite, &mut Transform)>,
+) {
+    let height = settings.row_height * 0.6;
+    let left = settings.node_width * 0.5 - METER_LENGTH;
+    for (meter, mut sprite, mut transform) in &mut q_meters {
+        let Some(level) = level_meters.0.level(meter.node, meter.channel) else {
+            sprite.color = Color::NONE;
+            continue;
+        };
+        let clipping = level.peak >= 1.;
+        if meter.peak {
+            transform.translation.x = left + meter_fraction(level.peak) * METER_LENGTH;
+            sprite.custom_size = Some(Vec2::new(2., height));
+            sprite.color = if clipping { Color::RED } else { Color::WHITE };
+        } else {
+            sprite.custom_size = Some(Vec2::new(meter_fraction(level.rms) * METER_LENGTH, height));
+            sprite.color = if clipping {
+                Color::rgba(1., 0.2, 0.2, 0.8)
+            } else {
+                Color::rgba(0.2, 0.9, 0.3, 0.8)
+            };
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Overview of the whole graph in a corner of the window.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    camera::CameraTransition, node_height, node_size, GameCamera, GraphInputs, GraphOutputs, Node,
+    VisualiserSettings,
+};
+
+/// Space around the graph inside the minimap, in pixels
+const PADDING: f32 = 6.;
+
+/// The minimap panel. Holds how world coordinates map onto it so that clicks
+/// can be mapped back.
+#[derive(Component, Default)]
+pub(crate) struct Minimap {
+    /// World position shown in the top left corner of the minimap
+    world_top_left: Vec2,
+    /// Minimap pixels per world unit
+    scale: f32,
+    /// Offset centering the graph in the minimap, in pixels
+    offset: Vec2,
+}
+
+impl Minimap {
+    fn to_minimap(&self, world: Vec2) -> Vec2 {
+        // World y grows upwards, UI y downwards
+        let relative = Vec2::new(
+            world.x - self.world_top_left.x,
+            self.world_top_left.y - world.y,
+        );
+        self.offset + relative * self.scale
+    }
+    fn to_world(&self, minimap: Vec2) -> Vec2 {
+        let relative = (minimap - self.offset) / self.scale;
+        Vec2::new(
+            self.world_top_left.x + relative.x,
+            self.world_top_left.y - relative.y,
+        )
+    }
+}
+
+/// A node drawn on the minimap. They are reused from frame to frame.
+#[derive(Component)]
+pub(crate) struct MinimapNode;
+
+/// The part of the graph currently seen through the camera
+#[derive(Component)]
+pub(crate) struct MinimapViewport;
+
+pub(crate) fn setup_minimap(mut commands: Commands, settings: Res<VisualiserSettings>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(10.),
+                    right: Val::Px(10.),
+                    width: Val::Px(settings.minimap_size.x),
+                    height: Val::Px(settings.minimap_size.y),
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..default()
+            },
+            Interaction::default(),
+            Minimap::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        border: UiRect::all(Val::Px(1.)),
+                        ..default()
+                    },
+                    border_color: Color::WHITE.into(),
+                    z_index: ZIndex::Local(1),
+                    ..default()
+                },
+                MinimapViewport,
+            ));
+        });
+}
+
+/// Show or hide the minimap with [`VisualiserSettings::minimap_key`]
+pub(crate) fn toggle_minimap(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    mut q_minimap: Query<&mut Visibility, With<Minimap>>,
+) {
+    if !settings
+        .minimap_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        return;
+    }
+    for mut visibility in &mut q_minimap {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn place(style: &mut Style, minimap: &Minimap, rect: Rect) {
+    let top_left = minimap.to_minimap(Vec2::new(rect.min.x, rect.max.y));
+    let size = rect.size() * minimap.scale;
+    style.left = Val::Px(top_left.x);
+    style.top = Val::Px(top_left.y);
+    // Keep tiny nodes visible
+    style.width = Val::Px(size.x.max(1.));
+    style.height = Val::Px(size.y.max(1.));
+}
+
+pub(crate) fn update_minimap(
+    mut commands: Commands,
+    settings: Res<VisualiserSettings>,
+    q_camera: Query<(&Camera, &Transform, &OrthographicProjection), With<GameCamera>>,
+    q_nodes: Query<(&Node, &Transform)>,
+    q_graph_outputs: Query<(&GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(&GraphInputs, &Transform)>,
+    mut q_minimap: Query<(Entity, &mut Minimap, &Visibility)>,
+    mut q_minimap_nodes: Query<
+        (Entity, &mut Style, &mut BackgroundColor),
+        (With<MinimapNode>, Without<MinimapViewport>),
+    >,
+    mut q_viewport: Query<&mut Style, With<MinimapViewport>>,
+) {
+    let Ok((minimap_entity, mut minimap, visibility)) = q_minimap.get_single_mut() else {
+        return;
+    };
+    if visibility == Visibility::Hidden {
+        return;
+    }
+    let Ok((camera, camera_transform, projection)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(viewport_size) = camera.logical_viewport_size() else {
+        return;
+    };
+    let viewport = Rect::from_center_size(
+        camera_transform.translation.xy(),
+        viewport_size * projection.scale,
+    );
+    let io_size = |num_channels| {
+        Vec2::new(
+            settings.node_width,
+            node_height(num_channels, num_channels, &settings),
+        )
+    };
+    let rects: Vec<(Rect, Color)> = q_nodes
+        .iter()
+        .map(|(node, transform)| {
+            (
+                Rect::from_center_size(transform.translation.xy(), node_size(node, &settings)),
+                Color::GRAY,
+            )
+        })
+        .chain(q_graph_outputs.iter().map(|(go, transform)| {
+            (
+                Rect::from_center_size(transform.translation.xy(), io_size(go.num_outputs)),
+                Color::ORANGE_RED,
+            )
+        }))
+        .chain(q_graph_inputs.iter().map(|(gi, transform)| {
+            (
+                Rect::from_center_size(transform.translation.xy(), io_size(gi.num_inputs)),
+                Color::ORANGE_RED,
+            )
+        }))
+        .collect();
+
+    // Fit the graph and the viewport into the minimap, keeping the aspect ratio
+    let bounds = rects
+        .iter()
+        .map(|(rect, _)| *rect)
+        .fold(viewport, |a, b| a.union(b));
+    let available = settings.minimap_size - Vec2::splat(PADDING * 2.);
+    let scale = (available / bounds.size()).min_element();
+    minimap.world_top_left = Vec2::new(bounds.min.x, bounds.max.y);
+    minimap.scale = scale;
+    minimap.offset = Vec2::splat(PADDING) + (available - bounds.size() * scale) * 0.5;
+
+    if let Ok(mut style) = q_viewport.get_single_mut() {
+        place(&mut style, &minimap, viewport);
+    }
+    let mut existing = q_minimap_nodes.iter_mut();
+    for (rect, color) in rects {
+        match existing.next() {
+            Some((_, mut style, mut background)) => {
+                place(&mut style, &minimap, rect);
+                *background = color.into();
+            }
+            None => {
+                let mut style = Style {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                };
+                place(&mut style, &minimap, rect);
+                let child = commands
+                    .spawn((
+                        NodeBundle {
+                            style,
+                            background_color: color.into(),
+                            ..default()
+                        },
+                        MinimapNode,
+                    ))
+                    .id();
+                commands.entity(minimap_entity).add_child(child);
+            }
+        }
+    }
+    for (entity, _, _) in existing {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Move the camera to wherever the minimap is clicked or dragged
+pub(crate) fn click_minimap(
+    mouse: Res<Input<MouseButton>>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_minimap: Query<(&Minimap, &Interaction, &bevy::ui::Node, &GlobalTransform)>,
+    mut q_camera: Query<&mut Transform, With<GameCamera>>,
+    mut transition: ResMut<CameraTransition>,
+) {
+    if !mouse.pressed(MouseButton::Left) {
+        return;
+    }
+    let Ok((minimap, interaction, ui_node, ui_transform)) = q_minimap.get_single() else {
+        return;
+    };
+    if *interaction != Interaction::Pressed {
+        return;
+    }
+    let Some(cursor) = q_windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
+    let Ok(mut transform) = q_camera.get_single_mut() else {
+        return;
+    };
+    // UI transforms are at the center of the node
+    let top_left = ui_transform.translation().xy() - ui_node.size() * 0.5;
+    let world = minimap.to_world(cursor - top_left);
+    transform.translation = world.extend(transform.translation.z);
+    transition.cancel();
+}
@@ -0,0 +1,185 @@
+//! Moving in and out of nested graphs.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use knyst::{graph::NodeId, inspection::GraphInspection};
+
+use crate::{
+    cursor_over_ui, cursor_world_position, node_size, GameCamera, Graph, InspectionUpdated,
+    KnystData, Node, VisualiserSettings,
+};
+
+/// Two clicks on the same node within this many seconds count as a double click
+const DOUBLE_CLICK_TIME: f32 = 0.4;
+
+/// Which graph is currently shown, as the path of graph nodes leading to it
+/// from the top level graph. An empty path shows the top level graph.
+#[derive(Resource, Default)]
+pub(crate) struct GraphView {
+    pub(crate) path: Vec<NodeId>,
+}
+
+impl GraphView {
+    /// Return the inspection of the shown graph and how many steps of the
+    /// path could be followed. If the path leads to a graph that no longer
+    /// exists, the deepest graph that still exists is returned.
+    pub(crate) fn resolve<'a>(
+        &self,
+        top_level: &'a GraphInspection,
+    ) -> (&'a GraphInspection, usize) {
+        let mut inspection = top_level;
+        for (depth, id) in self.path.iter().enumerate() {
+            let inner = inspection
+                .nodes
+                .iter()
+                .find(|node| node.address == *id)
+                .and_then(|node| node.graph_inspection.as_ref());
+            match inner {
+                Some(inner) => inspection = inner,
+                None => return (inspection, depth),
+            }
+        }
+        (inspection, self.path.len())
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Breadcrumbs;
+
+/// Clicking it shows the graph at this depth of the [`GraphView`] path
+#[derive(Component)]
+pub(crate) struct Breadcrumb(usize);
+
+pub(crate) fn setup_breadcrumbs(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(5.),
+                left: Val::Px(5.),
+                column_gap: Val::Px(8.),
+                ..default()
+            },
+            ..default()
+        },
+        Breadcrumbs,
+    ));
+}
+
+/// Enter a sub-graph when its node is double clicked
+pub(crate) fn enter_graph_on_double_click(
+    mouse: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    settings: Res<VisualiserSettings>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_graph_nodes: Query<(Entity, &Node, &GlobalTransform), With<Graph>>,
+    q_interactions: Query<&Interaction>,
+    mut graph_view: ResMut<GraphView>,
+    mut last_click: Local<Option<(Entity, f32)>>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || cursor_over_ui(&q_interactions) {
+        return;
+    }
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    let clicked = q_graph_nodes.iter().find(|(_, node, transform)| {
+        Rect::from_center_size(transform.translation().xy(), node_size(node, &settings))
+            .contains(cursor)
+    });
+    let Some((entity, node, _)) = clicked else {
+        *last_click = None;
+        return;
+    };
+    let now = time.elapsed_seconds();
+    match *last_click {
+        Some((last_entity, last_time))
+            if last_entity == entity && now - last_time < DOUBLE_CLICK_TIME =>
+        {
+            graph_view.path.push(node.id);
+            *last_click = None;
+        }
+        _ => *last_click = Some((entity, now)),
+    }
+}
+
+/// Go back up to the parent graph using breadcrumbs or the backspace key
+pub(crate) fn leave_graph(
+    keys: Res<Input<KeyCode>>,
+    q_breadcrumbs: Query<(&Interaction, &Breadcrumb), Changed<Interaction>>,
+    mut graph_view: ResMut<GraphView>,
+) {
+    if keys.just_pressed(KeyCode::Back) && !graph_view.path.is_empty() {
+        graph_view.path.pop();
+    }
+    for (interaction, breadcrumb) in &q_breadcrumbs {
+        if *interaction == Interaction::Pressed && breadcrumb.0 < graph_view.path.len() {
+            graph_view.path.truncate(breadcrumb.0);
+        }
+    }
+}
+
+/// Rebuild the breadcrumbs whenever the shown graph changes
+pub(crate) fn update_breadcrumbs(
+    mut commands: Commands,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    q_breadcrumbs: Query<Entity, With<Breadcrumbs>>,
+    asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+    mut inspection_updates: EventReader<InspectionUpdated>,
+    mut shown_crumbs: Local<Vec<(String, u64)>>,
+) {
+    let inspection_updated = inspection_updates.read().count() > 0;
+    if !graph_view.is_changed() && !inspection_updated {
+        return;
+    }
+    let Ok(container) = q_breadcrumbs.get_single() else {
+        return;
+    };
+    let text_style = TextStyle {
+        font: asset_server.load(&settings.font_path),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+    // Collect the name and graph id of every graph along the path
+    let mut crumbs = vec![("Graph".to_string(), knyst_data.latest_inspection.graph_id)];
+    let mut inspection = &knyst_data.latest_inspection;
+    for id in &graph_view.path {
+        let Some(node) = inspection.nodes.iter().find(|node| node.address == *id) else {
+            break;
+        };
+        let Some(inner) = &node.graph_inspection else {
+            break;
+        };
+        crumbs.push((node.name.clone(), inner.graph_id));
+        inspection = inner;
+    }
+    if *shown_crumbs == crumbs {
+        return;
+    }
+    *shown_crumbs = crumbs.clone();
+
+    commands.entity(container).despawn_descendants();
+    let last = crumbs.len() - 1;
+    commands.entity(container).with_children(|parent| {
+        for (depth, (name, graph_id)) in crumbs.into_iter().enumerate() {
+            let label = if depth == last {
+                format!("{name} [{graph_id}]")
+            } else {
+                format!("{name} [{graph_id}] >")
+            };
+            parent
+                .spawn((
+                    ButtonBundle {
+                        background_color: Color::NONE.into(),
+                        ..default()
+                    },
+                    Breadcrumb(depth),
+                ))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section(label, text_style.clone()));
+                });
+        }
+    });
+}
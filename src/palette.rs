@@ -0,0 +1,395 @@
+//! Searchable palette for adding new nodes to the running graph.
+//!
+//! Like the other edits, nothing is added locally: the node is pushed to
+//! knyst and shows up with the next inspection, at the place the palette was
+//! opened.
+
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use knyst::{
+    controller::KnystCommands,
+    envelope::Envelope,
+    gen::delay::static_sample_delay,
+    graph::NodeId,
+    handles::{handle, Handle, HandleData, MulGen, PowfGen},
+    knyst,
+    prelude::{oscillator, WavetableId},
+};
+
+use crate::{
+    cursor_world_position, editing::InputEditor, navigation::GraphView, GameCamera, KnystData,
+    RequestInspection, VisualiserSettings,
+};
+
+/// A kind of node that can be added from the node palette
+#[derive(Clone)]
+pub struct PaletteEntry {
+    name: String,
+    category: String,
+    create: Arc<dyn Fn() -> Option<NodeId> + Send + Sync>,
+}
+
+impl PaletteEntry {
+    /// `create` is called with the shown graph selected in [`knyst()`], so
+    /// the node should be pushed with the default knyst commands. It returns
+    /// the id of the new node, or `None` if it couldn't be added.
+    pub fn new(
+        name: impl Into<String>,
+        category: impl Into<String>,
+        create: impl Fn() -> Option<NodeId> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            category: category.into(),
+            create: Arc::new(create),
+        }
+    }
+    /// True if every word of `query` is part of the name or the category
+    fn matches(&self, query: &str) -> bool {
+        let name = self.name.to_lowercase();
+        let category = self.category.to_lowercase();
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| name.contains(word) || category.contains(word))
+    }
+}
+
+/// The first node of a handle, which is the node a single gen handle wraps
+fn first_node<H: HandleData + Copy>(handle: Handle<H>) -> Option<NodeId> {
+    handle.node_ids().next()
+}
+
+/// The nodes that can always be added
+pub(crate) fn default_entries() -> Vec<PaletteEntry> {
+    vec![
+        PaletteEntry::new("Sine", "oscillator", || {
+            first_node(oscillator(WavetableId::cos()))
+        }),
+        PaletteEntry::new("Multiply", "math", || {
+            Some(knyst().push_without_inputs(MulGen(1)))
+        }),
+        PaletteEntry::new("Power", "math", || {
+            Some(knyst().push_without_inputs(PowfGen(1)))
+        }),
+        PaletteEntry::new("Envelope", "envelope", || {
+            let envelope = Envelope {
+                points: vec![(1.0, 0.005), (0.0, 0.5)],
+                ..Default::default()
+            };
+            first_node(handle(envelope.to_gen()))
+        }),
+        PaletteEntry::new("Sample delay", "delay", || {
+            first_node(static_sample_delay(48 * 500))
+        }),
+    ]
+}
+
+/// Everything the palette can add: the default entries followed by the ones
+/// added to the plugin
+#[derive(Resource, Clone)]
+pub(crate) struct PaletteEntries(pub(crate) Vec<PaletteEntry>);
+
+impl PaletteEntries {
+    /// Indices of the entries matching `query`, at most `max_results`
+    fn matching(&self, query: &str, max_results: usize) -> Vec<usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.matches(query))
+            .map(|(index, _)| index)
+            .take(max_results)
+            .collect()
+    }
+}
+
+/// State of the node palette
+#[derive(Resource, Default)]
+pub(crate) struct NodePalette {
+    /// Where the palette was opened in world and window coordinates, or
+    /// `None` while it is closed
+    opened_at: Option<(Vec2, Vec2)>,
+    query: String,
+    /// Index of the highlighted result, which Enter adds
+    highlighted: usize,
+}
+
+impl NodePalette {
+    /// True while the palette is open and key presses are text input
+    pub(crate) fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+    /// Add `entry` at the position the palette was opened and close it
+    fn choose(&mut self, entry: usize, chosen: &mut EventWriter<ChosenEntry>) {
+        if let Some((position, _)) = self.opened_at.take() {
+            chosen.send(ChosenEntry { entry, position });
+        }
+    }
+}
+
+/// An entry was chosen to be added at `position`
+#[derive(Event)]
+pub(crate) struct ChosenEntry {
+    entry: usize,
+    position: Vec2,
+}
+
+/// Positions for added nodes that haven't shown up in an inspection yet
+#[derive(Resource, Default)]
+pub(crate) struct Placements(HashMap<NodeId, Vec2>);
+
+impl Placements {
+    /// Where `node` was added, if it was added from the palette
+    pub(crate) fn take(&mut self, node: NodeId) -> Option<Vec2> {
+        self.0.remove(&node)
+    }
+}
+
+/// The palette panel, hidden while the palette is closed
+#[derive(Component)]
+pub(crate) struct PalettePanel;
+
+#[derive(Component)]
+pub(crate) struct PaletteQueryText;
+
+/// One row of the results, showing the result at this index
+#[derive(Component)]
+pub(crate) struct PaletteItem(usize);
+
+pub(crate) fn setup_palette(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<VisualiserSettings>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load(&settings.font_path),
+        font_size: 14.0,
+        color: Color::WHITE,
+    };
+    let category_style = TextStyle {
+        color: Color::GRAY,
+        ..text_style.clone()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    min_width: Val::Px(200.),
+                    padding: UiRect::all(Val::Px(6.)),
+                    row_gap: Val::Px(2.),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.85).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            Interaction::default(),
+            PalettePanel,
+        ))
+        .with_children(|panel| {
+            panel.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                PaletteQueryText,
+            ));
+            for index in 0..settings.palette_max_results {
+                panel
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                display: Display::None,
+                                justify_content: JustifyContent::SpaceBetween,
+                                column_gap: Val::Px(10.),
+                                padding: UiRect::horizontal(Val::Px(4.)),
+                                ..default()
+                            },
+                            background_color: Color::NONE.into(),
+                            ..default()
+                        },
+                        PaletteItem(index),
+                    ))
+                    .with_children(|item| {
+                        item.spawn(TextBundle::from_sections([
+                            TextSection::new("", text_style.clone()),
+                            TextSection::new("", category_style.clone()),
+                        ]));
+                    });
+            }
+        });
+}
+
+/// Open the palette at the cursor and type the search query. The arrow keys
+/// move the highlight through the results and Enter adds the highlighted one.
+pub(crate) fn type_palette_query(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    settings: Res<VisualiserSettings>,
+    entries: Res<PaletteEntries>,
+    input_editor: Res<InputEditor>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    mut palette: ResMut<NodePalette>,
+    mut chosen: EventWriter<ChosenEntry>,
+) {
+    if !palette.is_open() {
+        let Some(key) = settings.palette_key.filter(|key| keys.just_pressed(*key)) else {
+            return;
+        };
+        if input_editor.is_editing() {
+            return;
+        }
+        let screen = q_windows
+            .get_single()
+            .ok()
+            .and_then(|window| window.cursor_position());
+        let (Some(screen), Some(world)) = (screen, cursor_world_position(&q_windows, &q_camera))
+        else {
+            return;
+        };
+        palette.opened_at = Some((world, screen));
+        palette.query.clear();
+        palette.highlighted = 0;
+        // The key opening the palette isn't part of the query
+        characters.clear();
+        keys.reset(key);
+        return;
+    }
+    let query_before = palette.query.clone();
+    for event in characters.read() {
+        if !event.char.is_control() {
+            palette.query.push(event.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        palette.query.pop();
+    }
+    if palette.query != query_before {
+        palette.highlighted = 0;
+    }
+    let results = entries.matching(&palette.query, settings.palette_max_results);
+    if keys.just_pressed(KeyCode::Down) {
+        palette.highlighted = (palette.highlighted + 1).min(results.len().saturating_sub(1));
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        palette.highlighted = palette.highlighted.saturating_sub(1);
+    }
+    if keys.just_pressed(KeyCode::Return) || keys.just_pressed(KeyCode::NumpadEnter) {
+        if let Some(&entry) = results.get(palette.highlighted) {
+            palette.choose(entry, &mut chosen);
+        }
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        palette.opened_at = None;
+    }
+    keys.reset_all();
+}
+
+/// Add the clicked result, or close the palette when clicking next to it
+pub(crate) fn click_palette(
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    entries: Res<PaletteEntries>,
+    q_panel: Query<&Interaction, With<PalettePanel>>,
+    q_items: Query<(&Interaction, &PaletteItem), Changed<Interaction>>,
+    mut palette: ResMut<NodePalette>,
+    mut chosen: EventWriter<ChosenEntry>,
+) {
+    if !palette.is_open() {
+        return;
+    }
+    let results = entries.matching(&palette.query, settings.palette_max_results);
+    for (interaction, item) in &q_items {
+        match interaction {
+            Interaction::Pressed => {
+                if let Some(&entry) = results.get(item.0) {
+                    palette.choose(entry, &mut chosen);
+                    return;
+                }
+            }
+            Interaction::Hovered => palette.highlighted = item.0,
+            Interaction::None => (),
+        }
+    }
+    let over_panel = q_panel
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if mouse.just_pressed(MouseButton::Left) && !over_panel {
+        palette.opened_at = None;
+    }
+}
+
+pub(crate) fn update_palette(
+    settings: Res<VisualiserSettings>,
+    entries: Res<PaletteEntries>,
+    palette: Res<NodePalette>,
+    mut q_panel: Query<&mut Style, (With<PalettePanel>, Without<PaletteItem>)>,
+    mut q_query_text: Query<&mut Text, With<PaletteQueryText>>,
+    mut q_items: Query<(&PaletteItem, &mut Style, &mut BackgroundColor, &Children)>,
+    mut q_item_texts: Query<&mut Text, Without<PaletteQueryText>>,
+) {
+    if !palette.is_changed() {
+        return;
+    }
+    let Ok(mut panel_style) = q_panel.get_single_mut() else {
+        return;
+    };
+    let Some((_, screen)) = palette.opened_at else {
+        panel_style.display = Display::None;
+        return;
+    };
+    panel_style.display = Display::Flex;
+    panel_style.left = Val::Px(screen.x);
+    panel_style.top = Val::Px(screen.y);
+    if let Ok(mut text) = q_query_text.get_single_mut() {
+        text.sections[0].value = format!("Add node: {}_", palette.query);
+    }
+    let results = entries.matching(&palette.query, settings.palette_max_results);
+    for (item, mut style, mut background, children) in &mut q_items {
+        let Some(entry) = results.get(item.0).and_then(|&entry| entries.0.get(entry)) else {
+            style.display = Display::None;
+            continue;
+        };
+        style.display = Display::Flex;
+        *background = if item.0 == palette.highlighted {
+            Color::rgb(0.15, 0.15, 0.3).into()
+        } else {
+            Color::NONE.into()
+        };
+        for child in children {
+            if let Ok(mut text) = q_item_texts.get_mut(*child) {
+                text.sections[0].value = entry.name.clone();
+                text.sections[1].value = format!("  {}", entry.category);
+            }
+        }
+    }
+}
+
+/// Push the chosen nodes to the shown graph and remember where to put them
+pub(crate) fn add_chosen_nodes(
+    mut chosen: EventReader<ChosenEntry>,
+    entries: Res<PaletteEntries>,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    mut placements: ResMut<Placements>,
+    mut inspection_requests: EventWriter<RequestInspection>,
+) {
+    for chosen in chosen.read() {
+        let Some(entry) = entries.0.get(chosen.entry) else {
+            continue;
+        };
+        let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+        knyst().to_graph(inspection.graph_id);
+        let node = (entry.create)();
+        knyst().to_top_level_graph();
+        match node {
+            Some(node) => {
+                placements.0.insert(node, chosen.position);
+                inspection_requests.send(RequestInspection);
+            }
+            None => warn!("Couldn't add {}", entry.name),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Oscilloscope showing the recent waveform of the probed output.
+
+use bevy::prelude::*;
+
+use crate::{
+    capture::{Probe, SampleCaptures},
+    VisualiserSettings,
+};
+
+/// The scope panel, hidden while no output is probed
+#[derive(Component)]
+pub(crate) struct ScopePanel;
+
+#[derive(Component)]
+pub(crate) struct ScopeTitle;
+
+#[derive(Component)]
+pub(crate) struct ScopeCloseButton;
+
+/// One pixel wide column of the waveform, spanning the samples that fall in it
+#[derive(Component)]
+pub(crate) struct ScopeColumn(usize);
+
+/// Spawn the scope panel as part of the probe panels
+pub(crate) fn spawn_scope(
+    parent: &mut ChildBuilder,
+    text_style: &TextStyle,
+    settings: &VisualiserSettings,
+) {
+    let size = settings.scope_size;
+    parent
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(4.)),
+                    row_gap: Val::Px(4.),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..default()
+            },
+            Interaction::default(),
+            ScopePanel,
+        ))
+        .with_children(|panel| {
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        justify_content: JustifyContent::SpaceBetween,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|header| {
+                    header.spawn((TextBundle::from_section("", text_style.clone()), ScopeTitle));
+                    header
+                        .spawn((
+                            ButtonBundle {
+                                background_color: Color::NONE.into(),
+                                ..default()
+                            },
+                            ScopeCloseButton,
+                        ))
+                        .with_children(|button| {
+                            button.spawn(TextBundle::from_section("x", text_style.clone()));
+                        });
+                });
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(size.x),
+                        height: Val::Px(size.y),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.05, 0.05, 0.05).into(),
+                    ..default()
+                })
+                .with_children(|plot| {
+                    for x in 0..size.x as usize {
+                        plot.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    position_type: PositionType::Absolute,
+                                    left: Val::Px(x as f32),
+                                    width: Val::Px(1.),
+                                    ..default()
+                                },
+                                background_color: Color::GREEN.into(),
+                                ..default()
+                            },
+                            ScopeColumn(x),
+                        ));
+                    }
+                });
+        });
+}
+
+pub(crate) fn close_scope(
+    q_close: Query<&Interaction, (Changed<Interaction>, With<ScopeCloseButton>)>,
+    mut probe: ResMut<Probe>,
+) {
+    if q_close
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        probe.output = None;
+    }
+}
+
+/// Index of the first sample where the signal rises through its mean among
+/// the first `search` samples, so that periodic signals stand still
+fn trigger_index(samples: &[f32], search: usize) -> Option<usize> {
+    let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+    (1..search.min(samples.len())).find(|&i| samples[i - 1] < mean && samples[i] >= mean)
+}
+
+pub(crate) fn update_scope(
+    probe: Res<Probe>,
+    captures: Res<SampleCaptures>,
+    settings: Res<VisualiserSettings>,
+    mut q_panel: Query<&mut Style, (With<ScopePanel>, Without<ScopeColumn>)>,
+    mut q_title: Query<&mut Text, With<ScopeTitle>>,
+    mut q_columns: Query<(&ScopeColumn, &mut Style)>,
+) {
+    let Ok(mut panel_style) = q_panel.get_single_mut() else {
+        return;
+    };
+    let Some(output) = &probe.output else {
+        panel_style.display = Display::None;
+        return;
+    };
+    panel_style.display = Display::Flex;
+
+    let window = settings.scope_samples.max(1);
+    // Capture twice the window so that there is room to look for a trigger
+    let samples = captures
+        .0
+        .latest_samples(output.node, output.channel, window * 2);
+    let start = if samples.len() > window {
+        trigger_index(&samples, samples.len() - window).unwrap_or(samples.len() - window)
+    } else {
+        0
+    };
+    let shown = &samples[start..(start + window).min(samples.len())];
+    // Scale to the loudest sample so that control signals in Hz fit as well
+    let range = shown
+        .iter()
+        .fold(0., |max: f32, s| max.max(s.abs()))
+        .max(f32::EPSILON);
+    if let Ok(mut title) = q_title.get_single_mut() {
+        title.sections[0].value = format!("{}  ±{:.3}", output.label, range);
+    }
+
+    let size = settings.scope_size;
+    let to_y = |sample: f32| ((1. - sample / range) * 0.5 * size.y).clamp(0., size.y);
+    let num_columns = size.x.max(1.);
+    for (column, mut style) in &mut q_columns {
+        let from = (column.0 as f32 / num_columns * shown.len() as f32) as usize;
+        let to = ((column.0 + 1) as f32 / num_columns * shown.len() as f32) as usize;
+        // Include the last sample of the previous column so that the columns
+        // join up into a continuous line
+        let span =
+            &shown[from.saturating_sub(1).min(shown.len())..to.max(from + 1).min(shown.len())];
+        if span.is_empty() {
+            style.height = Val::Px(0.);
+            continue;
+        }
+        let (low, high) = span.iter().fold((f32::MAX, f32::MIN), |(low, high), s| {
+            (low.min(*s), high.max(*s))
+        });
+        let top = to_y(high);
+        style.top = Val::Px(top);
+        style.height = Val::Px((to_y(low) - top).max(1.));
+    }
+}
@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::EdgeStyle;
+
+/// Everything about the look and behaviour of the visualiser that can be tuned
+/// without touching the systems themselves.
+///
+/// Build one with the `with_*` methods and hand it to
+/// [`KnystVisualiserPlugin::with_settings`](crate::KnystVisualiserPlugin::with_settings).
+/// It is available as a [`Resource`] while the app is running. Keys,
+/// intervals and the layout and edge settings can be changed on the fly, but
+/// the font and the sizes of nodes and panels are only read when they are
+/// spawned, so set those before the app starts.
+///
+/// The mouse bindings are fixed: clicking an output port probes it, dragging
+/// from an output port to an input port connects them, and clicking an input
+/// port edits its value.
+#[derive(Resource, Clone, Debug)]
+pub struct VisualiserSettings {
+    /// Size of the window opened by [`KnystVisualiserPlugin::run`](crate::KnystVisualiserPlugin::run)
+    pub window_size: Vec2,
+    /// Title of the window opened by [`KnystVisualiserPlugin::run`](crate::KnystVisualiserPlugin::run)
+    pub window_title: String,
+    /// Font used for all text, relative to the Bevy asset folder
+    pub font_path: String,
+    /// How often to check whether a requested inspection has arrived
+    pub inspection_poll_interval: Duration,
+    /// How long to wait between inspections of the running graph
+    pub reinspection_interval: Duration,
+    /// Key that requests a new inspection right away, if any
+    pub reinspect_key: Option<KeyCode>,
+    /// Key that switches to the next [`LayoutMode`](crate::LayoutMode), if any
+    pub layout_mode_key: Option<KeyCode>,
+    /// Key that releases all nodes pinned by dragging them, if any
+    pub unpin_all_key: Option<KeyCode>,
+    /// Key that zooms the camera to show the whole graph, if any
+    pub fit_all_key: Option<KeyCode>,
+    /// Key that zooms the camera to show the selected nodes, if any
+    pub fit_selection_key: Option<KeyCode>,
+    /// Key that disconnects the selected edges in the running graph, if any
+    pub disconnect_key: Option<KeyCode>,
+    /// Key that shows or hides the minimap, if any
+    pub minimap_key: Option<KeyCode>,
+    /// Key that opens the palette for adding nodes at the cursor, if any
+    pub palette_key: Option<KeyCode>,
+    /// Number of matching entries listed in the node palette at most
+    pub palette_max_results: usize,
+    /// Size of the minimap in the bottom right corner in pixels
+    pub minimap_size: Vec2,
+    /// Size of the plot in the oscilloscope panel in pixels
+    pub scope_size: Vec2,
+    /// Number of samples shown across the oscilloscope
+    pub scope_samples: usize,
+    /// Size of the plot in the spectrum panel in pixels
+    pub spectrum_size: Vec2,
+    /// Number of samples analysed for the spectrum, rounded up to a power of two
+    pub spectrum_window_size: usize,
+    /// Time constant of the averaging of the spectrum, zero for no averaging
+    pub spectrum_averaging: Duration,
+    /// Width of the node inspector panel in pixels
+    pub inspector_width: f32,
+    /// How much dragging an input value changes it per pixel, as a fraction
+    /// of the value
+    pub value_drag_speed: f32,
+    /// Width of a node rectangle in pixels
+    pub node_width: f32,
+    /// Height of one input/output channel row in pixels
+    pub row_height: f32,
+    /// Horizontal space between two columns of nodes
+    pub column_gap: f32,
+    /// Vertical space between two nodes in the same column
+    pub row_gap: f32,
+    /// Vertical space between groups of nodes that aren't connected to each other
+    pub component_gap: f32,
+    /// How the lines between nodes are drawn
+    pub edge_style: EdgeStyle,
+    /// How long nodes take to move to a new position chosen by the layout
+    pub transition_duration: Duration,
+    /// Where the GraphOutputs node is placed. The rest of the graph grows
+    /// leftwards from here.
+    pub graph_outputs_position: Vec2,
+}
+
+impl Default for VisualiserSettings {
+    fn default() -> Self {
+        Self {
+            window_size: Vec2::new(1280., 720.),
+            window_title: "Knyst visualiser".to_string(),
+            font_path: "fonts/Terminess (TTF) Bold Nerd Font Complete.ttf".to_string(),
+            inspection_poll_interval: Duration::ZERO,
+            reinspection_interval: Duration::from_millis(500),
+            reinspect_key: Some(KeyCode::R),
+            layout_mode_key: Some(KeyCode::L),
+            unpin_all_key: Some(KeyCode::U),
+            fit_all_key: Some(KeyCode::Home),
+            fit_selection_key: Some(KeyCode::F),
+            disconnect_key: Some(KeyCode::Delete),
+            minimap_key: Some(KeyCode::M),
+            palette_key: Some(KeyCode::Tab),
+            palette_max_results: 12,
+            minimap_size: Vec2::new(240., 160.),
+            scope_size: Vec2::new(320., 120.),
+            scope_samples: 1024,
+            spectrum_size: Vec2::new(320., 120.),
+            spectrum_window_size: 2048,
+            spectrum_averaging: Duration::from_millis(100),
+            inspector_width: 280.,
+            value_drag_speed: 0.005,
+            node_width: 160.,
+            row_height: 15.,
+            column_gap: 20.,
+            row_gap: 10.,
+            component_gap: 40.,
+            edge_style: EdgeStyle::Curved,
+            transition_duration: Duration::from_millis(400),
+            graph_outputs_position: Vec2::new(500., 0.),
+        }
+    }
+}
+
+impl VisualiserSettings {
+    pub fn with_window_size(mut self, width: f32, height: f32) -> Self {
+        self.window_size = Vec2::new(width, height);
+        self
+    }
+    pub fn with_window_title(mut self, title: impl Into<String>) -> Self {
+        self.window_title = title.into();
+        self
+    }
+    pub fn with_font_path(mut self, path: impl Into<String>) -> Self {
+        self.font_path = path.into();
+        self
+    }
+    pub fn with_inspection_poll_interval(mut self, interval: Duration) -> Self {
+        self.inspection_poll_interval = interval;
+        self
+    }
+    pub fn with_reinspection_interval(mut self, interval: Duration) -> Self {
+        self.reinspection_interval = interval;
+        self
+    }
+    pub fn with_reinspect_key(mut self, key: Option<KeyCode>) -> Self {
+        self.reinspect_key = key;
+        self
+    }
+    pub fn with_layout_mode_key(mut self, key: Option<KeyCode>) -> Self {
+        self.layout_mode_key = key;
+        self
+    }
+    pub fn with_unpin_all_key(mut self, key: Option<KeyCode>) -> Self {
+        self.unpin_all_key = key;
+        self
+    }
+    pub fn with_fit_all_key(mut self, key: Option<KeyCode>) -> Self {
+        self.fit_all_key = key;
+        self
+    }
+    pub fn with_fit_selection_key(mut self, key: Option<KeyCode>) -> Self {
+        self.fit_selection_key = key;
+        self
+    }
+    pub fn with_disconnect_key(mut self, key: Option<KeyCode>) -> Self {
+        self.disconnect_key = key;
+        self
+    }
+    pub fn with_minimap_key(mut self, key: Option<KeyCode>) -> Self {
+        self.minimap_key = key;
+        self
+    }
+    pub fn with_palette_key(mut self, key: Option<KeyCode>) -> Self {
+        self.palette_key = key;
+        self
+    }
+    pub fn with_palette_max_results(mut self, max_results: usize) -> Self {
+        self.palette_max_results = max_results;
+        self
+    }
+    pub fn with_minimap_size(mut self, width: f32, height: f32) -> Self {
+        self.minimap_size = Vec2::new(width, height);
+        self
+    }
+    pub fn with_scope_size(mut self, width: f32, height: f32) -> Self {
+        self.scope_size = Vec2::new(width, height);
+        self
+    }
+    pub fn with_scope_samples(mut self, num_samples: usize) -> Self {
+        self.scope_samples = num_samples;
+        self
+    }
+    pub fn with_spectrum_size(mut self, width: f32, height: f32) -> Self {
+        self.spectrum_size = Vec2::new(width, height);
+        self
+    }
+    pub fn with_spectrum_window_size(mut self, num_samples: usize) -> Self {
+        self.spectrum_window_size = num_samples;
+        self
+    }
+    pub fn with_spectrum_averaging(mut self, averaging: Duration) -> Self {
+        self.spectrum_averaging = averaging;
+        self
+    }
+    pub fn with_inspector_width(mut self, width: f32) -> Self {
+        self.inspector_width = width;
+        self
+    }
+    pub fn with_value_drag_speed(mut self, speed: f32) -> Self {
+        self.value_drag_speed = speed;
+        self
+    }
+    pub fn with_node_width(mut self, width: f32) -> Self {
+        self.node_width = width;
+        self
+    }
+    pub fn with_row_height(mut self, height: f32) -> Self {
+        self.row_height = height;
+        self
+    }
+    pub fn with_column_gap(mut self, gap: f32) -> Self {
+        self.column_gap = gap;
+        self
+    }
+    pub fn with_row_gap(mut self, gap: f32) -> Self {
+        self.row_gap = gap;
+        self
+    }
+    pub fn with_component_gap(mut self, gap: f32) -> Self {
+        self.component_gap = gap;
+        self
+    }
+    pub fn with_edge_style(mut self, style: EdgeStyle) -> Self {
+        self.edge_style = style;
+        self
+    }
+    pub fn with_transition_duration(mut self, duration: Duration) -> Self {
+        self.transition_duration = duration;
+        self
+    }
+    pub fn with_graph_outputs_position(mut self, position: Vec2) -> Self {
+        self.graph_outputs_position = position;
+        self
+    }
+
+    /// Distance between the x positions of two neighbouring columns
+    pub fn column_width(&self) -> f32 {
+        self.node_width + self.column_gap
+    }
+}
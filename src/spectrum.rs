@@ -0,0 +1,378 @@
+//! Spectrum analyser showing the frequency content of the probed output.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+    capture::{Probe, SampleCaptures},
+    VisualiserSettings,
+};
+
+/// Lowest frequency on the spectrum's log frequency axis, in Hz
+const MIN_FREQUENCY: f32 = 20.;
+/// Levels at the bottom and the top of the spectrum, in dB
+const MIN_DB: f32 = -100.;
+const MAX_DB: f32 = 0.;
+/// Frequencies marked below the spectrum
+const FREQUENCY_LABELS: [(f32, &str); 4] =
+    [(20., "20"), (100., "100"), (1000., "1k"), (10000., "10k")];
+
+/// The spectrum panel, shown next to the scope
+#[derive(Component)]
+pub(crate) struct SpectrumPanel;
+
+#[derive(Component)]
+pub(crate) struct SpectrumCloseButton;
+
+/// One pixel wide bar of the spectrum
+#[derive(Component)]
+pub(crate) struct SpectrumColumn(usize);
+
+/// A frequency marked on the axis, positioned once the sample rate is known
+#[derive(Component)]
+pub(crate) struct FrequencyLabel(f32);
+
+/// Whether the spectrum is open, and the averaged magnitudes it shows
+#[derive(Resource)]
+pub(crate) struct SpectrumState {
+    open: bool,
+    /// Power of every FFT bin up to the Nyquist frequency, relative to a
+    /// full scale sine
+    averaged: Vec<f32>,
+}
+
+impl Default for SpectrumState {
+    fn default() -> Self {
+        Self {
+            open: true,
+            averaged: Vec::new(),
+        }
+    }
+}
+
+/// Spawn the spectrum panel as part of the probe panels
+pub(crate) fn spawn_spectrum(
+    parent: &mut ChildBuilder,
+    text_style: &TextStyle,
+    settings: &VisualiserSettings,
+) {
+    let size = settings.spectrum_size;
+    let label_style = TextStyle {
+        font_size: 10.,
+        ..text_style.clone()
+    };
+    parent
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::None,
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(4.)),
+                    row_gap: Val::Px(4.),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..default()
+            },
+            Interaction::default(),
+            SpectrumPanel,
+        ))
+        .with_children(|panel| {
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        justify_content: JustifyContent::SpaceBetween,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|header| {
+                    header.spawn(TextBundle::from_section("Spectrum", text_style.clone()));
+                    header
+                        .spawn((
+                            ButtonBundle {
+                                background_color: Color::NONE.into(),
+                                ..default()
+                            },
+                            SpectrumCloseButton,
+                        ))
+                        .with_children(|button| {
+                            button.spawn(TextBundle::from_section("x", text_style.clone()));
+                        });
+                });
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(size.x),
+                        height: Val::Px(size.y),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.05, 0.05, 0.05).into(),
+                    ..default()
+                })
+                .with_children(|plot| {
+                    for x in 0..size.x as usize {
+                        plot.spawn((
+                            NodeBundle {
+                                style: Style {
+                                    position_type: PositionType::Absolute,
+                                    left: Val::Px(x as f32),
+                                    bottom: Val::Px(0.),
+                                    width: Val::Px(1.),
+                                    ..default()
+                                },
+                                background_color: Color::CYAN.into(),
+                                ..default()
+                            },
+                            SpectrumColumn(x),
+                        ));
+                    }
+                });
+            panel
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(size.x),
+                        height: Val::Px(label_style.font_size),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|axis| {
+                    for (frequency, label) in FREQUENCY_LABELS {
+                        axis.spawn((
+                            TextBundle::from_section(label, label_style.clone()).with_style(
+                                Style {
+                                    position_type: PositionType::Absolute,
+                                    ..default()
+                                },
+                            ),
+                            FrequencyLabel(frequency),
+                        ));
+                    }
+                });
+        });
+}
+
+pub(crate) fn close_spectrum(
+    q_close: Query<&Interaction, (Changed<Interaction>, With<SpectrumCloseButton>)>,
+    probe: Res<Probe>,
+    mut state: ResMut<SpectrumState>,
+) {
+    // Probing another output opens the spectrum again
+    if probe.is_changed() {
+        state.open = true;
+        state.averaged.clear();
+    }
+    if q_close
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        state.open = false;
+    }
+}
+
+/// In place radix 2 FFT. `buffer` holds complex numbers as (re, im) and its
+/// length has to be a power of two.
+fn fft(buffer: &mut [Vec2]) {
+    let n = buffer.len();
+    if n <= 1 {
+        return;
+    }
+    // Bit reversal permutation
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            buffer.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        let angle = -2. * PI / length as f32;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let twiddle = Vec2::from_angle(angle * k as f32);
+                let even = buffer[start + k];
+                let odd = twiddle.rotate(buffer[start + k + length / 2]);
+                buffer[start + k] = even + odd;
+                buffer[start + k + length / 2] = even - odd;
+            }
+        }
+        length *= 2;
+    }
+}
+
+/// Power of the positive frequency bins of `samples`, Hann windowed and
+/// relative to a full scale sine
+fn power_spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    let window = |i: usize| 0.5 - 0.5 * (2. * PI * i as f32 / n as f32).cos();
+    let mut buffer: Vec<Vec2> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| Vec2::new(s * window(i), 0.))
+        .collect();
+    fft(&mut buffer);
+    // Scale so that a full scale sine reads 0 dB
+    let window_sum: f32 = (0..n).map(window).sum();
+    buffer[..n / 2]
+        .iter()
+        .map(|bin| (bin.length() * 2. / window_sum).powi(2))
+        .collect()
+}
+
+fn power_to_db(power: f32) -> f32 {
+    10. * power.max(1e-20).log10()
+}
+
+/// Magnitudes in dB of the positive frequency bins of `samples`
+#[cfg(test)]
+fn magnitude_spectrum(samples: &[f32]) -> Vec<f32> {
+    power_spectrum(samples)
+        .into_iter()
+        .map(power_to_db)
+        .collect()
+}
+
+/// Horizontal position of `frequency` on a log axis `width` pixels wide
+fn frequency_to_x(frequency: f32, nyquist: f32, width: f32) -> f32 {
+    (frequency / MIN_FREQUENCY).ln() / (nyquist / MIN_FREQUENCY).ln() * width
+}
+
+pub(crate) fn update_spectrum(
+    time: Res<Time>,
+    probe: Res<Probe>,
+    captures: Res<SampleCaptures>,
+    settings: Res<VisualiserSettings>,
+    mut state: ResMut<SpectrumState>,
+    mut q_panel: Query<&mut Style, With<SpectrumPanel>>,
+    mut q_columns: Query<(&SpectrumColumn, &mut Style), Without<SpectrumPanel>>,
+    mut q_labels: Query<
+        (&FrequencyLabel, &mut Style, &mut Visibility),
+        (Without<SpectrumPanel>, Without<SpectrumColumn>),
+    >,
+) {
+    let Ok(mut panel_style) = q_panel.get_single_mut() else {
+        return;
+    };
+    let Some(output) = probe.output.as_ref().filter(|_| state.open) else {
+        panel_style.display = Display::None;
+        return;
+    };
+    panel_style.display = Display::Flex;
+
+    let size = settings.spectrum_size;
+    let nyquist = captures.0.sample_rate() * 0.5;
+    for (label, mut style, mut visibility) in &mut q_labels {
+        let x = frequency_to_x(label.0, nyquist, size.x);
+        *visibility = if label.0 < nyquist {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        style.left = Val::Px(x);
+    }
+
+    let window_size = settings.spectrum_window_size.max(2).next_power_of_two();
+    let samples = captures
+        .0
+        .latest_samples(output.node, output.channel, window_size);
+    if samples.len() < window_size {
+        // Not enough recorded yet
+        return;
+    }
+    let spectrum = power_spectrum(&samples);
+    if state.averaged.len() != spectrum.len() {
+        state.averaged = spectrum;
+    } else {
+        // Average power rather than dB, over the same time at any frame rate
+        let time_constant = settings.spectrum_averaging.as_secs_f32();
+        let keep = if time_constant > 0. {
+            (-time.delta_seconds() / time_constant).exp()
+        } else {
+            0.
+        };
+        for (averaged, new) in state.averaged.iter_mut().zip(spectrum) {
+            *averaged = *averaged * keep + new * (1. - keep);
+        }
+    }
+
+    let bin_width = nyquist / state.averaged.len() as f32;
+    let bin_at = |x: f32| {
+        let frequency = MIN_FREQUENCY * (nyquist / MIN_FREQUENCY).powf(x / size.x);
+        ((frequency / bin_width) as usize).min(state.averaged.len() - 1)
+    };
+    for (column, mut style) in &mut q_columns {
+        // Low frequencies span several columns per bin, high frequencies
+        // several bins per column. Show the loudest bin in the column.
+        let from = bin_at(column.0 as f32);
+        let to = bin_at(column.0 as f32 + 1.)
+            .max(from + 1)
+            .min(state.averaged.len());
+        let db = power_to_db(state.averaged[from..to].iter().copied().fold(0., f32::max));
+        let fraction = ((db - MIN_DB) / (MAX_DB - MIN_DB)).clamp(0., 1.);
+        style.height = Val::Px(fraction * size.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 1024;
+
+    #[test]
+    fn fft_of_impulse_is_flat() {
+        let mut buffer = vec![Vec2::ZERO; 64];
+        buffer[0] = Vec2::new(1., 0.);
+        fft(&mut buffer);
+        for bin in buffer {
+            assert!((bin - Vec2::new(1., 0.)).length() < 1e-5, "{bin}");
+        }
+    }
+
+    #[test]
+    fn full_scale_sine_peaks_at_0_db_in_its_bin() {
+        let bin = 43;
+        let samples: Vec<f32> = (0..N)
+            .map(|i| (2. * PI * bin as f32 * i as f32 / N as f32).sin())
+            .collect();
+        let spectrum = magnitude_spectrum(&samples);
+        assert_eq!(spectrum.len(), N / 2);
+        let peak = (0..spectrum.len())
+            .max_by(|a, b| spectrum[*a].total_cmp(&spectrum[*b]))
+            .unwrap();
+        assert_eq!(peak, bin);
+        assert!(spectrum[peak].abs() < 0.1, "{}", spectrum[peak]);
+        // The Hann window leaks into the neighbouring bins only
+        for (i, db) in spectrum.iter().enumerate() {
+            if i.abs_diff(bin) > 2 {
+                assert!(*db < -80., "bin {i}: {db}");
+            }
+        }
+    }
+
+    #[test]
+    fn sine_from_mock_capture() {
+        use crate::{MockSampleCapture, SampleCapture};
+        use knyst::graph::NodeId;
+
+        let sample_rate = 48000.;
+        let capture = MockSampleCapture::new(sample_rate, N);
+        let node = NodeId::new();
+        let bin = 100;
+        let frequency = bin as f32 * sample_rate / N as f32;
+        let samples: Vec<f32> = (0..N)
+            .map(|i| 0.5 * (2. * PI * frequency * i as f32 / sample_rate).sin())
+            .collect();
+        capture.push_samples(node, 0, &samples);
+        let spectrum = magnitude_spectrum(&capture.latest_samples(node, 0, N));
+        let peak = (0..spectrum.len())
+            .max_by(|a, b| spectrum[*a].total_cmp(&spectrum[*b]))
+            .unwrap();
+        assert_eq!(peak, bin);
+        // Half amplitude is 6 dB down
+        assert!((spectrum[peak] + 6.02).abs() < 0.1, "{}", spectrum[peak]);
+    }
+}
@@ -0,0 +1,357 @@
+//! Connecting and disconnecting nodes in the running graph.
+//!
+//! Nothing is changed locally: the commands are sent to knyst and the edges
+//! appear or disappear with the next inspection.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use knyst::{controller::KnystCommands, graph::Connection, knyst};
+
+use crate::{
+    cursor_over_ui, cursor_world_position, edge_end_port, edges, navigation::GraphView,
+    node_height, port_at, EdgeEnd, EdgeEndpoint, GameCamera, GraphInputs, GraphOutputs, KnystData,
+    Node, NodeEdge, RequestInspection, VisualiserSettings,
+};
+
+/// How close to an edge a click has to be to select it, in screen pixels
+const EDGE_HIT_DISTANCE: f32 = 6.;
+
+/// An edge clicked on. It can be removed with
+/// [`VisualiserSettings::disconnect_key`].
+#[derive(Component)]
+pub struct SelectedEdge;
+
+/// The output a new connection is being dragged from
+#[derive(Resource, Default)]
+pub(crate) struct ConnectionDrag {
+    from: Option<(Entity, usize)>,
+}
+
+impl ConnectionDrag {
+    pub(crate) fn is_dragging(&self) -> bool {
+        self.from.is_some()
+    }
+}
+
+/// Something that has ports edges can be drawn between
+struct PortOwner {
+    entity: Entity,
+    endpoint: EdgeEndpoint,
+    center: Vec2,
+    num_inputs: usize,
+    num_outputs: usize,
+}
+
+fn port_owners(
+    q_nodes: &Query<(Entity, &Node, &Transform)>,
+    q_graph_outputs: &Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: &Query<(Entity, &GraphInputs, &Transform)>,
+) -> Vec<PortOwner> {
+    q_nodes
+        .iter()
+        .map(|(entity, node, transform)| PortOwner {
+            entity,
+            endpoint: EdgeEndpoint::Node(node.id),
+            center: transform.translation.xy(),
+            num_inputs: node.num_inputs,
+            num_outputs: node.num_outputs,
+        })
+        // The graph outputs are inputs to draw edges to and the graph inputs
+        // outputs to draw edges from
+        .chain(
+            q_graph_outputs
+                .iter()
+                .map(|(entity, go, transform)| PortOwner {
+                    entity,
+                    endpoint: EdgeEndpoint::GraphOutputs,
+                    center: transform.translation.xy(),
+                    num_inputs: go.num_outputs,
+                    num_outputs: 0,
+                }),
+        )
+        .chain(
+            q_graph_inputs
+                .iter()
+                .map(|(entity, gi, transform)| PortOwner {
+                    entity,
+                    endpoint: EdgeEndpoint::GraphInputs,
+                    center: transform.translation.xy(),
+                    num_inputs: 0,
+                    num_outputs: gi.num_inputs,
+                }),
+        )
+        .collect()
+}
+
+/// The knyst connection for an edge between two endpoints
+fn connection(
+    source: EdgeEndpoint,
+    from_index: usize,
+    sink: EdgeEndpoint,
+    to_index: usize,
+) -> Option<Connection> {
+    let connection = match (source, sink) {
+        (EdgeEndpoint::Node(source), EdgeEndpoint::Node(sink)) => source.to(sink),
+        (EdgeEndpoint::Node(source), EdgeEndpoint::GraphOutputs) => {
+            Connection::graph_output(&source)
+        }
+        (EdgeEndpoint::GraphInputs, EdgeEndpoint::Node(sink)) => Connection::graph_input(&sink),
+        _ => return None,
+    };
+    Some(connection.from_channel(from_index).to_channel(to_index))
+}
+
+/// Start dragging a new connection when the button is pressed on an output port
+pub(crate) fn start_connection_drag(
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_nodes: Query<(Entity, &Node, &Transform)>,
+    q_graph_outputs: Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(Entity, &GraphInputs, &Transform)>,
+    q_interactions: Query<&Interaction>,
+    mut connection_drag: ResMut<ConnectionDrag>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || cursor_over_ui(&q_interactions) {
+        return;
+    }
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    connection_drag.from = port_owners(&q_nodes, &q_graph_outputs, &q_graph_inputs)
+        .iter()
+        .find_map(|owner| {
+            let height = node_height(owner.num_inputs, owner.num_outputs, &settings);
+            port_at(
+                cursor,
+                owner.center,
+                height,
+                owner.num_outputs,
+                true,
+                &settings,
+            )
+            .map(|channel| (owner.entity, channel))
+        });
+}
+
+/// Connect to the input port the connection is dropped on
+pub(crate) fn finish_connection_drag(
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_nodes: Query<(Entity, &Node, &Transform)>,
+    q_graph_outputs: Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(Entity, &GraphInputs, &Transform)>,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    mut connection_drag: ResMut<ConnectionDrag>,
+    mut inspection_requests: EventWriter<RequestInspection>,
+) {
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    let Some((from_entity, from_index)) = connection_drag.from.take() else {
+        return;
+    };
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    let owners = port_owners(&q_nodes, &q_graph_outputs, &q_graph_inputs);
+    let Some(source) = owners
+        .iter()
+        .find(|owner| owner.entity == from_entity)
+        .map(|owner| owner.endpoint)
+    else {
+        return;
+    };
+    let Some((sink, to_index)) = owners
+        .iter()
+        .filter(|owner| owner.entity != from_entity)
+        .find_map(|owner| {
+            let height = node_height(owner.num_inputs, owner.num_outputs, &settings);
+            port_at(
+                cursor,
+                owner.center,
+                height,
+                owner.num_inputs,
+                false,
+                &settings,
+            )
+            .map(|channel| (owner.endpoint, channel))
+        })
+    else {
+        return;
+    };
+    match connection(source, from_index, sink, to_index) {
+        Some(connection) => {
+            // Connect in the shown graph rather than the selected one
+            let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+            knyst().to_graph(inspection.graph_id);
+            knyst().connect(connection);
+            knyst().to_top_level_graph();
+            inspection_requests.send(RequestInspection);
+        }
+        None => warn!("Can't connect {source:?} to {sink:?}"),
+    }
+}
+
+/// Show the connection being dragged
+pub(crate) fn draw_connection_drag(
+    mut gizmos: Gizmos,
+    settings: Res<VisualiserSettings>,
+    connection_drag: Res<ConnectionDrag>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_edge_ends: Query<EdgeEnd>,
+) {
+    let Some((from_entity, from_index)) = connection_drag.from else {
+        return;
+    };
+    let (Ok(from), Some(cursor)) = (
+        q_edge_ends.get(from_entity),
+        cursor_world_position(&q_windows, &q_camera),
+    ) else {
+        return;
+    };
+    let start = edge_end_port(from, from_index, true, &settings);
+    let path = edges::edge_path(
+        start,
+        cursor,
+        settings.edge_style,
+        settings.column_gap * 0.5,
+    );
+    edges::draw_edge_path(&mut gizmos, &path, Color::WHITE, true);
+}
+
+/// Distance from `point` to the line segment between `a` and `b`
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0. {
+        ((point - a).dot(ab) / ab.length_squared()).clamp(0., 1.)
+    } else {
+        0.
+    };
+    point.distance(a + ab * t)
+}
+
+/// Select the edge closest to a click, or clear the selection when the click
+/// isn't close to any edge
+pub(crate) fn select_edge_on_click(
+    mut commands: Commands,
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<VisualiserSettings>,
+    connection_drag: Res<ConnectionDrag>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    q_projection: Query<&OrthographicProjection, With<GameCamera>>,
+    q_edge_ends: Query<EdgeEnd>,
+    q_nodes: Query<(Entity, &Node, &Transform)>,
+    q_graph_outputs: Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(Entity, &GraphInputs, &Transform)>,
+    q_edges: Query<(Entity, &NodeEdge, Has<SelectedEdge>)>,
+    q_interactions: Query<&Interaction>,
+) {
+    if !mouse.just_pressed(MouseButton::Left)
+        || connection_drag.is_dragging()
+        || cursor_over_ui(&q_interactions)
+    {
+        return;
+    }
+    let Some(cursor) = cursor_world_position(&q_windows, &q_camera) else {
+        return;
+    };
+    // Clicks on nodes are for the nodes
+    let on_node = port_owners(&q_nodes, &q_graph_outputs, &q_graph_inputs)
+        .iter()
+        .any(|owner| {
+            Rect::from_center_size(
+                owner.center,
+                Vec2::new(
+                    settings.node_width,
+                    node_height(owner.num_inputs, owner.num_outputs, &settings),
+                ),
+            )
+            .contains(cursor)
+        });
+    let scale = q_projection.get_single().map_or(1., |p| p.scale);
+    let hit = if on_node {
+        None
+    } else {
+        q_edges
+            .iter()
+            .filter_map(|(entity, edge, _)| {
+                let path = edges::path_of(edge, &q_edge_ends, &settings)?;
+                let distance = path
+                    .windows(2)
+                    .map(|segment| distance_to_segment(cursor, segment[0], segment[1]))
+                    .fold(f32::MAX, f32::min);
+                (distance <= EDGE_HIT_DISTANCE * scale).then_some((entity, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entity, _)| entity)
+    };
+    for (entity, _, selected) in &q_edges {
+        if selected && Some(entity) != hit {
+            commands.entity(entity).remove::<SelectedEdge>();
+        }
+    }
+    if let Some(entity) = hit {
+        commands.entity(entity).insert(SelectedEdge);
+    }
+}
+
+/// Disconnect the selected edges in the running graph
+pub(crate) fn disconnect_selected_edges(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<VisualiserSettings>,
+    q_selected: Query<&NodeEdge, With<SelectedEdge>>,
+    q_nodes: Query<(Entity, &Node, &Transform)>,
+    q_graph_outputs: Query<(Entity, &GraphOutputs, &Transform)>,
+    q_graph_inputs: Query<(Entity, &GraphInputs, &Transform)>,
+    knyst_data: NonSend<KnystData>,
+    graph_view: Res<GraphView>,
+    mut inspection_requests: EventWriter<RequestInspection>,
+) {
+    if !settings
+        .disconnect_key
+        .is_some_and(|key| keys.just_pressed(key))
+    {
+        return;
+    }
+    let owners = port_owners(&q_nodes, &q_graph_outputs, &q_graph_inputs);
+    let endpoint = |entity| {
+        owners
+            .iter()
+            .find(|owner| owner.entity == entity)
+            .map(|owner| owner.endpoint)
+    };
+    let (inspection, _) = graph_view.resolve(&knyst_data.latest_inspection);
+    for edge in &q_selected {
+        let (Some(source), Some(sink)) = (endpoint(edge.from_entity), endpoint(edge.to_entity))
+        else {
+            continue;
+        };
+        if let Some(connection) =
+            connection(source, edge.from_channel_index, sink, edge.to_channel_index)
+        {
+            knyst().to_graph(inspection.graph_id);
+            knyst().disconnect(connection);
+            knyst().to_top_level_graph();
+            inspection_requests.send(RequestInspection);
+        }
+    }
+}
+
+pub(crate) fn draw_selected_edges(
+    mut gizmos: Gizmos,
+    settings: Res<VisualiserSettings>,
+    q_edge_ends: Query<EdgeEnd>,
+    q_selected: Query<&NodeEdge, With<SelectedEdge>>,
+) {
+    for edge in &q_selected {
+        if let Some(path) = edges::path_of(edge, &q_edge_ends, &settings) {
+            edges::draw_edge_path(&mut gizmos, &path, Color::WHITE, false);
+        }
+    }
+}